@@ -0,0 +1,103 @@
+//! Deterministic synthetic images shared by the benchmarks, so they don't depend on external files
+
+/// The kinds of image benchmarked, chosen to exercise different opcodes
+#[derive(Copy, Clone, Debug)]
+pub enum Pattern {
+    /// A single color, encoded almost entirely as runs
+    Solid,
+    /// Large flat regions, as in screenshots and UI
+    Flat,
+    /// Smooth gradients, encoded mostly as small differences
+    Gradient,
+    /// Smooth gradients with a little per-pixel noise, standing in for a photograph
+    Photographic,
+    /// A handful of colors in small blocks, as in pixel art, encoded mostly as index lookups
+    Palette,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 5] = [
+        Pattern::Solid,
+        Pattern::Flat,
+        Pattern::Gradient,
+        Pattern::Photographic,
+        Pattern::Palette,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pattern::Solid => "solid",
+            Pattern::Flat => "flat",
+            Pattern::Gradient => "gradient",
+            Pattern::Photographic => "photographic",
+            Pattern::Palette => "palette",
+        }
+    }
+}
+
+/// Generates a tightly packed RGBA image. The same arguments always produce the same pixels
+pub fn synthetic_rgba(pattern: Pattern, width: usize, height: usize) -> Vec<u8> {
+    let mut seed: u32 = 0x9E37_79B9;
+    let mut noise = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+
+    const PALETTE: [[u8; 4]; 8] = [
+        [20, 12, 28, 255],
+        [68, 36, 52, 255],
+        [48, 52, 109, 255],
+        [78, 74, 78, 255],
+        [133, 76, 48, 255],
+        [52, 101, 36, 255],
+        [208, 70, 72, 255],
+        [222, 238, 214, 255],
+    ];
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let px = match pattern {
+                Pattern::Solid => [90, 140, 200, 255],
+                Pattern::Flat => {
+                    let shade = if (x * 6 / width + y * 5 / height).is_multiple_of(2) {
+                        40
+                    } else {
+                        220
+                    };
+                    [shade, shade, shade, 255]
+                }
+                Pattern::Gradient => [
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    ((x + y) * 255 / (width + height)) as u8,
+                    255,
+                ],
+                Pattern::Photographic => [
+                    ((x * 255 / width) as u8).saturating_add((noise() % 5) as u8),
+                    ((y * 255 / height) as u8).saturating_add((noise() % 5) as u8),
+                    (((x + y) % 200) as u8).saturating_add((noise() % 5) as u8),
+                    255,
+                ],
+                Pattern::Palette => {
+                    // Hash the 4x4 block so neighbouring blocks rarely share a color
+                    let block = (x / 4) as u32 ^ ((y / 4) as u32).wrapping_mul(0x2545_F491);
+                    PALETTE[(block.wrapping_mul(0x9E37_79B9) >> 29) as usize]
+                }
+            };
+            data.extend_from_slice(&px);
+        }
+    }
+    data
+}
+
+/// Drops the alpha channel from RGBA data
+#[allow(dead_code)]
+pub fn to_rgb(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .flat_map(|px| &px[..3])
+        .copied()
+        .collect()
+}
@@ -0,0 +1,78 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{decode, encode_to_vec, ChannelCount, DecoderState};
+use std::hint::black_box;
+
+mod common;
+use common::{synthetic_rgba, Pattern};
+
+/// (width, height) of the images benchmarked for each pattern
+const SIZES: [(usize, usize); 3] = [(320, 240), (1920, 1080), (3840, 2160)];
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+const FRAMES: usize = 100;
+
+/// A bar sweeping across a gradient, standing in for the frames of an animation
+fn animation_frames() -> Vec<Vec<u8>> {
+    (0..FRAMES)
+        .map(|frame| {
+            let mut data = Vec::with_capacity(WIDTH * HEIGHT * 4);
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let bar = (x + WIDTH - frame * 3 % WIDTH) % WIDTH < 16;
+                    let shade = if bar { 255 } else { (y * 255 / HEIGHT) as u8 };
+                    data.extend_from_slice(&[shade, (x * 255 / WIDTH) as u8, frame as u8, 255]);
+                }
+            }
+            encode_to_vec(&data, WIDTH, ChannelCount::Rgba).unwrap()
+        })
+        .collect()
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (width, height) in SIZES {
+        for pattern in Pattern::ALL {
+            let data = synthetic_rgba(pattern, width, height);
+            let encoded = encode_to_vec(&data, width, ChannelCount::Rgba).unwrap();
+            group.throughput(Throughput::Bytes(data.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new(pattern.name(), format!("{}x{}", width, height)),
+                &encoded,
+                |b, encoded| {
+                    b.iter(|| decode(black_box(&encoded[..]), ChannelCount::Rgba).unwrap())
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_frames(c: &mut Criterion) {
+    let frames = animation_frames();
+
+    let mut group = c.benchmark_group("decode_frames");
+    group.throughput(Throughput::Bytes((WIDTH * HEIGHT * 4 * FRAMES) as u64));
+    group.bench_function("decode", |b| {
+        b.iter(|| {
+            for frame in &frames {
+                black_box(decode(black_box(&frame[..]), ChannelCount::Rgba).unwrap());
+            }
+        })
+    });
+    group.bench_function("decoder_state", |b| {
+        let mut state = DecoderState::new();
+        b.iter(|| {
+            for frame in &frames {
+                state
+                    .decode_into(black_box(&frame[..]), ChannelCount::Rgba)
+                    .unwrap();
+                black_box(state.data());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_frames);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi_rs::{encode_to_vec, ChannelCount};
+use std::hint::black_box;
+
+mod common;
+use common::{synthetic_rgba, to_rgb, Pattern};
+
+/// (width, height) of the images benchmarked for each pattern
+const SIZES: [(usize, usize); 2] = [(320, 240), (1920, 1080)];
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for (width, height) in SIZES {
+        for pattern in Pattern::ALL {
+            let data = synthetic_rgba(pattern, width, height);
+            group.throughput(Throughput::Bytes(data.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new(pattern.name(), format!("{}x{}", width, height)),
+                &data,
+                |b, data| {
+                    b.iter(|| encode_to_vec(black_box(data), width, ChannelCount::Rgba).unwrap())
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_encode_rgb(c: &mut Criterion) {
+    let (width, height) = SIZES[1];
+
+    let mut group = c.benchmark_group("encode_rgb");
+    for pattern in [Pattern::Flat, Pattern::Photographic] {
+        let data = to_rgb(&synthetic_rgba(pattern, width, height));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(pattern.name(), |b| {
+            b.iter(|| encode_to_vec(black_box(&data), width, ChannelCount::Rgb).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_encode_rgb);
+criterion_main!(benches);
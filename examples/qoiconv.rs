@@ -1,19 +1,43 @@
-use png::{BitDepth, ColorType};
-use qoi_rs::{ChannelCount, read_from_file, write_to_file};
-use std::{fs::File, io::{BufWriter, Result}, path::{Path, PathBuf}};
+use qoi_rs::ChannelCount;
+use std::{fmt::Display, io::{self, Result}, path::{Path, PathBuf}};
 
 fn main() -> Result<()> {
+    let help = "Usage: <img.png> <img.qoi> OR <img.qoi> <img.png> [--channels rgb|rgba|gray]\n       <source dir> <dest dir> [--to qoi|png] [--channels rgb|rgba|gray]";
+
+    let mut paths = vec![];
+    let mut channels = None;
+    let mut to = "qoi".to_string();
     let mut args = std::env::args().skip(1);
-    let help = "Usage: <img.png> <img.qoi> OR <img.qoi> <img.png>";
-    let source: PathBuf = args.next().expect(help).into();
-    let dest: PathBuf = args.next().expect(help).into();
+    while let Some(arg) = args.next() {
+        if arg == "--channels" {
+            channels = Some(match args.next().as_deref() {
+                Some("gray") => ChannelCount::Gray,
+                Some("rgb") => ChannelCount::Rgb,
+                Some("rgba") => ChannelCount::Rgba,
+                _ => panic!("{}", help),
+            });
+        } else if arg == "--to" {
+            to = args.next().filter(|t| t == "qoi" || t == "png").expect(help);
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+
+    let (source, dest) = match &paths[..] {
+        [source, dest] => (source.clone(), dest.clone()),
+        _ => panic!("{}", help),
+    };
+
+    if source.is_dir() {
+        return convert_dir(&source, &dest, &to, channels);
+    }
 
     let source_ext = source.extension().and_then(|e| e.to_str());
     let dest_ext = dest.extension().and_then(|e| e.to_str());
 
     match (source_ext, dest_ext) {
         (Some("png"), Some("qoi")) => png_to_qoi(source, dest),
-        (Some("qoi"), Some("png")) => qoi_to_png(source, dest, ChannelCount::Rgba),
+        (Some("qoi"), Some("png")) => qoi_to_png(source, dest, channels),
         _ => {
             eprintln!("{}", help);
             Ok(())
@@ -21,42 +45,77 @@ fn main() -> Result<()> {
     }
 }
 
-fn png_to_qoi(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<()> {
-    let decoder = png::Decoder::new(File::open(source)?);
-    let mut reader = decoder.read_info().unwrap();
-    let mut buf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut buf).unwrap();
-    let bytes = &buf[..info.buffer_size()];
-
-    assert_eq!(info.bit_depth, BitDepth::Eight);
-    let channels = match info.color_type {
-        ColorType::Rgb => ChannelCount::Rgb,
-        ColorType::Rgba => ChannelCount::Rgba,
-        other => panic!(
-            "Unsupported color type {:?}, supports only RGB, RGBA",
-            other
-        ),
-    };
+/// Converts every file under `source` with the opposite extension of `to` into the same
+/// relative path under `dest`. Failures are reported and skipped
+fn convert_dir(source: &Path, dest: &Path, to: &str, channels: Option<ChannelCount>) -> Result<()> {
+    let from = if to == "qoi" { "png" } else { "qoi" };
 
-    write_to_file(dest, bytes, info.width as _, channels)
-}
+    let mut files = vec![];
+    find_files(source, from, &mut files)?;
 
-fn qoi_to_png(source: impl AsRef<Path>, dest: impl AsRef<Path>, channels: ChannelCount) -> Result<()> {
-    let (data, width, height) = read_from_file(source, channels)?;
+    let (mut converted, mut failed, mut saved) = (0, 0, 0i64);
+    for file in files {
+        let out = dest.join(file.strip_prefix(source).unwrap()).with_extension(to);
+        let result = std::fs::create_dir_all(out.parent().unwrap()).and_then(|_| match to {
+            "qoi" => png_to_qoi(&file, &out),
+            _ => qoi_to_png(&file, &out, channels),
+        });
 
-    let file = File::create(dest)?;
-    let mut writer = BufWriter::new(file);
+        match result {
+            Ok(()) => {
+                converted += 1;
+                saved += std::fs::metadata(&file)?.len() as i64 - std::fs::metadata(&out)?.len() as i64;
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", file.display(), e);
+            }
+        }
+    }
 
-    let mut encoder = png::Encoder::new(&mut writer, width as _, height as _); // Width is 2 pixels and height is 1.
-    encoder.set_color(match channels {
-        ChannelCount::Rgb => png::ColorType::Rgb,
-        ChannelCount::Rgba => png::ColorType::Rgba,
-    });
-    encoder.set_depth(png::BitDepth::Eight);
+    eprintln!("Converted {} files ({} failed), saved {} bytes", converted, failed, saved);
 
-    let mut writer = encoder.write_header().unwrap();
+    Ok(())
+}
 
-    writer.write_image_data(&data).unwrap();
+/// Recursively collects the paths of files with the extension `ext`
+fn find_files(dir: &Path, ext: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, ext, files)?;
+        } else if path.extension().and_then(|e| e.to_str()) == Some(ext) {
+            files.push(path);
+        }
+    }
 
     Ok(())
 }
+
+/// Wraps a codec error so that a failed file doesn't abort a batch
+fn other(e: impl Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Prints the dimensions, channel count and bit depth of an 8 or 16-bit QOI file, whose headers
+/// both keep the first two at the same offsets. `channels` overrides the stored channel count
+fn describe(qoi: &[u8], channels: Option<ChannelCount>) {
+    let read_u32 = |at: usize| u32::from_le_bytes(qoi[at..at + 4].try_into().unwrap());
+    let channels = channels.unwrap_or_else(|| ChannelCount::try_from(qoi[13]).unwrap());
+    let depth = if qoi_rs::is_qoi16(qoi) { 16 } else { 8 };
+    eprintln!("{}x{}, {:?}, {}-bit", read_u32(5), read_u32(9), channels, depth);
+}
+
+fn png_to_qoi(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<()> {
+    let qoi = qoi_rs::png_to_qoi(&std::fs::read(source)?).map_err(other)?;
+    describe(&qoi, None);
+    std::fs::write(dest, qoi)
+}
+
+/// Decodes to `channels`, or to the channel count stored in the header if `None`
+fn qoi_to_png(source: impl AsRef<Path>, dest: impl AsRef<Path>, channels: Option<ChannelCount>) -> Result<()> {
+    let qoi = std::fs::read(source)?;
+    let png = qoi_rs::qoi_to_png(&qoi, channels).map_err(other)?;
+    describe(&qoi, channels);
+    std::fs::write(dest, png)
+}
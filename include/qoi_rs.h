@@ -0,0 +1,32 @@
+/* C bindings for qoi-rs, built with `cargo build --release --features capi` */
+#ifndef QOI_RS_H
+#define QOI_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define QOI_OK 0
+#define QOI_ERR_INVALID_ARGUMENT 1
+#define QOI_ERR_ENCODE 2
+#define QOI_ERR_DECODE 3
+#define QOI_ERR_TOO_LARGE 4
+#define QOI_ERR_PANIC 5
+
+/* Encodes tightly packed pixels with `channels` (1, 3 or 4) bytes each. Returns null on failure.
+ * Release the result with qoi_free(buf, *out_len) */
+uint8_t *qoi_encode(const uint8_t *data, size_t len, size_t width, uint8_t channels,
+                    size_t *out_len, int *error);
+
+/* Decodes a stream to tightly packed pixels with `channels` bytes each, converting from the
+ * channel count it was encoded with. Returns null on failure.
+ * Release the result with qoi_free(buf, qoi_decoded_len(*out_width, *out_height, channels)) */
+uint8_t *qoi_decode(const uint8_t *data, size_t len, uint8_t channels,
+                    uint32_t *out_width, uint32_t *out_height, int *error);
+
+/* Length of the buffer qoi_decode returns for these dimensions, or 0 for a bad channel count */
+size_t qoi_decoded_len(uint32_t width, uint32_t height, uint8_t channels);
+
+/* Releases a buffer from qoi_encode or qoi_decode. Does nothing for null */
+void qoi_free(uint8_t *buf, size_t len);
+
+#endif
@@ -0,0 +1,313 @@
+//! Animations: a sequence of same-sized frames, e.g. a sprite animation or a short clip.
+//!
+//! This is NOT a standard QOI file. It is a container around frames encoded back to back, each
+//! of which is a kind byte followed by a complete QOI stream with its own header:
+//!
+//! | Field        | Size                             |
+//! |--------------|----------------------------------|
+//! | Magic `qoia` | 4                                |
+//! | Version      | 1                                |
+//! | Frame count  | 4 (LE)                           |
+//! | Width        | 4 (LE)                           |
+//! | Height       | 4 (LE)                           |
+//! | Channels     | 1                                |
+//! | Frame delay  | 4 (LE), in milliseconds          |
+//! | Frames       | 1 + `QoiHeader::stream_len` each |
+//!
+//! A keyframe (kind 0) stores the frame's pixels. A delta frame (kind 1) stores the bytewise
+//! wrapping difference from the previous frame, which is mostly zeros when little changes between
+//! frames, e.g. in a screen recording, and so compresses to long runs. Decoding may start at any
+//! keyframe, see `AnimDecoder::skip_to_keyframe`.
+//!
+//! The magic differs from a single stream's `qoiv`, so `read_header` and everything built on it
+//! reject an animation with `BadMagic` rather than decoding its container header as an image.
+//! Use `is_animation` to tell the two apart.
+use crate::{
+    check_dimensions, decode, dimension_field, encode, read_header, ChannelCount, QoiError, Result,
+};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const ANIM_MAGIC: &[u8; 4] = b"qoia";
+/// Version of the container layout, bumped only when the table above changes. Each frame's stream
+/// carries its own version
+const ANIM_VERSION: u8 = 1;
+/// Byte offset of the frame count within the container header
+const FRAME_COUNT_OFFSET: u64 = 5;
+const ANIM_HEADER_SIZE: usize = 22;
+/// Frame kind: the stream holds the frame itself
+const KEYFRAME: u8 = 0;
+/// Frame kind: the stream holds the difference from the previous frame
+const DELTA_FRAME: u8 = 1;
+
+/// Returns true if `data` begins with an animation rather than a single QOI stream
+pub fn is_animation(data: &[u8]) -> bool {
+    data.starts_with(ANIM_MAGIC)
+}
+
+/// Writes an animation one frame at a time. The frame count is filled in by
+/// [`AnimEncoder::finish`], which must be called once the last frame is added
+pub struct AnimEncoder<W: Write + Seek> {
+    writer: W,
+    start: u64,
+    len: u64,
+    width: usize,
+    height: usize,
+    channels: ChannelCount,
+    frames: u32,
+    /// The last frame added, which delta frames are taken against
+    prev: Vec<u8>,
+}
+
+impl<W: Write + Seek> AnimEncoder<W> {
+    /// Writes the container header. Every frame is shown for `delay_ms` milliseconds
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        channels: ChannelCount,
+        delay_ms: u32,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(QoiError::ZeroDimension);
+        }
+        let width_field = dimension_field(width)?;
+        let height_field = dimension_field(height)?;
+
+        let start = writer.stream_position()?;
+        writer.write_all(ANIM_MAGIC)?;
+        writer.write_all(&[ANIM_VERSION])?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&width_field.to_le_bytes())?;
+        writer.write_all(&height_field.to_le_bytes())?;
+        writer.write_all(&[channels as u8])?;
+        writer.write_all(&delay_ms.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            start,
+            len: ANIM_HEADER_SIZE as u64,
+            width,
+            height,
+            channels,
+            frames: 0,
+            prev: Vec::new(),
+        })
+    }
+
+    /// Encodes the next frame as a keyframe. It must hold exactly `width * height` pixels
+    pub fn add_frame(&mut self, data: &[u8]) -> Result<()> {
+        self.write_frame(data, KEYFRAME)
+    }
+
+    /// Encodes the next frame as its difference from the previous one, which is much smaller when
+    /// few pixels change. The first frame is always a keyframe
+    pub fn add_delta_frame(&mut self, data: &[u8]) -> Result<()> {
+        if self.frames == 0 {
+            return self.write_frame(data, KEYFRAME);
+        }
+        self.write_frame(data, DELTA_FRAME)
+    }
+
+    fn write_frame(&mut self, data: &[u8], kind: u8) -> Result<()> {
+        let expected = self.width * self.height * self.channels.bytes_per_pixel();
+        if data.len() != expected {
+            return Err(QoiError::FrameSizeMismatch {
+                expected,
+                actual: data.len(),
+            });
+        }
+        let frames = self
+            .frames
+            .checked_add(1)
+            .ok_or(QoiError::InvalidContainer)?;
+
+        self.writer.write_all(&[kind])?;
+        let frame_len = if kind == DELTA_FRAME {
+            let delta: Vec<u8> = data
+                .iter()
+                .zip(&self.prev)
+                .map(|(px, prev)| px.wrapping_sub(*prev))
+                .collect();
+            encode(&mut self.writer, &delta, self.width, self.channels)?
+        } else {
+            encode(&mut self.writer, data, self.width, self.channels)?
+        };
+
+        self.len += 1 + frame_len;
+        self.frames = frames;
+        self.prev.clear();
+        self.prev.extend_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Fills in the frame count, leaving the writer at the end of the animation. Returns the total
+    /// number of bytes written
+    pub fn finish(mut self) -> Result<u64> {
+        self.writer
+            .seek(SeekFrom::Start(self.start + FRAME_COUNT_OFFSET))?;
+        self.writer.write_all(&self.frames.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.start + self.len))?;
+        Ok(self.len)
+    }
+}
+
+/// A decoded frame of an animation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The image data, with delta frames already applied to the previous frame
+    pub data: Vec<u8>,
+    /// Whether the frame was stored whole rather than as a delta
+    pub keyframe: bool,
+}
+
+/// Reads an animation, yielding each frame in turn. Iteration stops after the first error
+pub struct AnimDecoder<R: Read> {
+    reader: R,
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    delay_ms: u32,
+    frame_count: u32,
+    remaining: u32,
+    /// Kind of the next frame, if `skip_to_keyframe` has already read it
+    next_kind: Option<u8>,
+    /// The last decoded frame, which the next delta frame applies to
+    prev: Option<Vec<u8>>,
+}
+
+impl<R: Read> AnimDecoder<R> {
+    /// Reads the container header
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0; ANIM_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+
+        let magic: [u8; 4] = header[..4].try_into().unwrap();
+        if &magic != ANIM_MAGIC {
+            return Err(QoiError::BadMagic(magic));
+        }
+        if header[4] != ANIM_VERSION {
+            return Err(QoiError::UnsupportedVersion(header[4]));
+        }
+        let frame_count = read_u32(5);
+        let width = read_u32(9);
+        let height = read_u32(13);
+        let channels = ChannelCount::try_from(header[17])?;
+        let delay_ms = read_u32(18);
+
+        check_dimensions(width, height)?;
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            channels,
+            delay_ms,
+            frame_count,
+            remaining: frame_count,
+            next_kind: None,
+            prev: None,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// How long each frame is shown, in milliseconds
+    pub fn delay_ms(&self) -> u32 {
+        self.delay_ms
+    }
+
+    /// The number of frames in the animation, including any already read
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Skips frames without decoding them until the next one is a keyframe, from which decoding
+    /// can start afresh. Returns false, leaving nothing to decode, if no keyframe remains
+    pub fn skip_to_keyframe(&mut self) -> Result<bool> {
+        while self.remaining > 0 {
+            let kind = self.read_kind()?;
+            if kind == KEYFRAME {
+                self.next_kind = Some(kind);
+                return Ok(true);
+            }
+
+            let header = read_header(&mut self.reader)?;
+            let skipped = io::copy(
+                &mut (&mut self.reader).take(header.body_len()),
+                &mut io::sink(),
+            )?;
+            if skipped != header.body_len() {
+                return Err(QoiError::UnexpectedEof);
+            }
+            self.remaining -= 1;
+            self.prev = None;
+        }
+        Ok(false)
+    }
+
+    fn read_kind(&mut self) -> Result<u8> {
+        if let Some(kind) = self.next_kind.take() {
+            return Ok(kind);
+        }
+        let mut kind = [0];
+        self.reader.read_exact(&mut kind)?;
+        Ok(kind[0])
+    }
+
+    fn next_frame(&mut self) -> Result<Frame> {
+        let kind = self.read_kind()?;
+        if kind != KEYFRAME && kind != DELTA_FRAME {
+            return Err(QoiError::InvalidContainer);
+        }
+
+        let (mut data, width, height) = decode(&mut self.reader, self.channels)?;
+        if (width, height) != (self.width, self.height) {
+            return Err(QoiError::InvalidContainer);
+        }
+
+        if kind == DELTA_FRAME {
+            // A delta frame needs the frame before it, which the first frame or one following
+            // skipped frames doesn't have
+            let prev = self.prev.as_ref().ok_or(QoiError::InvalidContainer)?;
+            for (px, prev) in data.iter_mut().zip(prev) {
+                *px = px.wrapping_add(*prev);
+            }
+        }
+        self.prev = Some(data.clone());
+
+        Ok(Frame {
+            data,
+            keyframe: kind == KEYFRAME,
+        })
+    }
+}
+
+impl<R: Read> Iterator for AnimDecoder<R> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let frame = self.next_frame();
+        self.remaining = match frame {
+            Ok(_) => self.remaining - 1,
+            Err(_) => 0,
+        };
+        Some(frame)
+    }
+}
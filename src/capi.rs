@@ -0,0 +1,131 @@
+//! C bindings. Build with `cargo build --release --features capi` and link against the
+//! resulting `cdylib`; the declarations are in `include/qoi_rs.h`.
+//!
+//! Buffers returned by these functions are owned by Rust and must be released with `qoi_free`,
+//! not `free`. On failure they return null and store one of the `QOI_ERR_*` codes in `error`
+use crate::{decode_converted, encode_to_vec, uncompressed_len, ChannelCount, QoiError};
+use std::ffi::c_int;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+pub const QOI_OK: c_int = 0;
+/// A pointer argument was null, or the channel count was not 1, 3 or 4
+pub const QOI_ERR_INVALID_ARGUMENT: c_int = 1;
+/// The image could not be encoded, e.g. the data doesn't match the width
+pub const QOI_ERR_ENCODE: c_int = 2;
+/// The stream could not be decoded
+pub const QOI_ERR_DECODE: c_int = 3;
+/// The stream was decoded but exceeds the size limit of `decode`
+pub const QOI_ERR_TOO_LARGE: c_int = 4;
+/// The library panicked. This is a bug
+pub const QOI_ERR_PANIC: c_int = 5;
+
+/// Encodes `len` bytes of tightly packed pixel data. On success returns the encoded bytes and
+/// stores their length in `out_len`
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, and `out_len` and `error` must be valid for writes
+/// or null
+#[no_mangle]
+pub unsafe extern "C" fn qoi_encode(
+    data: *const u8,
+    len: usize,
+    width: usize,
+    channels: u8,
+    out_len: *mut usize,
+    error: *mut c_int,
+) -> *mut u8 {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        if data.is_null() || out_len.is_null() {
+            return Err(QOI_ERR_INVALID_ARGUMENT);
+        }
+        let channels = ChannelCount::try_from(channels).map_err(|_| QOI_ERR_INVALID_ARGUMENT)?;
+        let data = std::slice::from_raw_parts(data, len);
+        encode_to_vec(data, width, channels).map_err(|_| QOI_ERR_ENCODE)
+    }));
+
+    match finish(result, error) {
+        Some(buf) => {
+            *out_len = buf.len();
+            into_raw(buf)
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Decodes `len` bytes of a stream into tightly packed pixels with `channels` bytes each, whatever
+/// the channel count the stream was encoded with, as `decode_converted` does. On success returns the pixels and stores the dimensions in `out_width` and `out_height`. The
+/// buffer is `width * height * channels` bytes long
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, and `out_width`, `out_height` and `error` must be
+/// valid for writes or null
+#[no_mangle]
+pub unsafe extern "C" fn qoi_decode(
+    data: *const u8,
+    len: usize,
+    channels: u8,
+    out_width: *mut u32,
+    out_height: *mut u32,
+    error: *mut c_int,
+) -> *mut u8 {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        if data.is_null() || out_width.is_null() || out_height.is_null() {
+            return Err(QOI_ERR_INVALID_ARGUMENT);
+        }
+        let channels = ChannelCount::try_from(channels).map_err(|_| QOI_ERR_INVALID_ARGUMENT)?;
+        let data = std::slice::from_raw_parts(data, len);
+        decode_converted(data, channels).map_err(|err| match err {
+            QoiError::TooLarge { .. } => QOI_ERR_TOO_LARGE,
+            _ => QOI_ERR_DECODE,
+        })
+    }));
+
+    match finish(result, error) {
+        Some((buf, width, height)) => {
+            *out_width = width;
+            *out_height = height;
+            into_raw(buf)
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Releases a buffer returned by `qoi_encode` or `qoi_decode`. `len` is the length reported by
+/// `qoi_encode`, or `width * height * channels` for `qoi_decode`. Does nothing if `buf` is null
+///
+/// # Safety
+/// `buf` must have been returned by this library with length `len`, and not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn qoi_free(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+/// Returns the length in bytes of the buffer `qoi_decode` returns for these dimensions, or 0 if
+/// `channels` is invalid
+#[no_mangle]
+pub extern "C" fn qoi_decoded_len(width: u32, height: u32, channels: u8) -> usize {
+    ChannelCount::try_from(channels)
+        .map(|channels| uncompressed_len(width, height, channels))
+        .unwrap_or(0)
+}
+
+/// Stores the error code, turning a panic into `QOI_ERR_PANIC`
+unsafe fn finish<T>(result: std::thread::Result<Result<T, c_int>>, error: *mut c_int) -> Option<T> {
+    let (value, code) = match result {
+        Ok(Ok(value)) => (Some(value), QOI_OK),
+        Ok(Err(code)) => (None, code),
+        Err(_) => (None, QOI_ERR_PANIC),
+    };
+    if !error.is_null() {
+        *error = code;
+    }
+    value
+}
+
+/// Hands a buffer over to C. Its length must be passed back to `qoi_free`
+fn into_raw(buf: Vec<u8>) -> *mut u8 {
+    Box::into_raw(buf.into_boxed_slice()) as *mut u8
+}
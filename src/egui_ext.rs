@@ -0,0 +1,14 @@
+//! Conversion to `egui`'s image type, for display in egui and eframe apps
+use crate::{decode_converted, ChannelCount, Result};
+use egui::ColorImage;
+use std::io::Read;
+
+/// Decodes an image as a `ColorImage`, ready for `Context::load_texture`. Images stored as gray
+/// or RGB become opaque RGBA, since egui always uses four channels
+pub fn decode_color_image<R: Read>(reader: R) -> Result<ColorImage> {
+    let (data, width, height) = decode_converted(reader, ChannelCount::Rgba)?;
+    Ok(ColorImage::from_rgba_unmultiplied(
+        [width as usize, height as usize],
+        &data,
+    ))
+}
@@ -0,0 +1,52 @@
+//! Conversions to and from the `image` crate's types
+use crate::{decode_converted, encode_dims, encode_to_vec, ChannelCount, QoiError, Result};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use std::io::{Read, Seek, Write};
+
+/// Encodes an 8-bit grayscale, RGB or RGBA image. Grayscale with alpha is expanded to RGBA
+pub fn from_dynamic_image(img: &DynamicImage) -> Result<Vec<u8>> {
+    let width = img.width() as usize;
+    match img {
+        DynamicImage::ImageLuma8(buf) => encode_to_vec(buf, width, ChannelCount::Gray),
+        DynamicImage::ImageRgb8(buf) => encode_to_vec(buf, width, ChannelCount::Rgb),
+        DynamicImage::ImageRgba8(buf) => encode_to_vec(buf, width, ChannelCount::Rgba),
+        DynamicImage::ImageLumaA8(_) => encode_to_vec(&img.to_rgba8(), width, ChannelCount::Rgba),
+        other => {
+            let color = other.color();
+            let bits = color.bits_per_pixel() / color.channel_count() as u16;
+            Err(QoiError::UnsupportedBitDepth(bits as u8))
+        }
+    }
+}
+
+/// Encodes an RGBA image, taking the dimensions from the image. Returns the total number of bytes
+/// written
+pub fn encode_image<W: Write + Seek>(writer: W, img: &RgbaImage) -> Result<u64> {
+    let (width, height) = img.dimensions();
+    encode_dims(
+        writer,
+        img.as_raw(),
+        width as usize,
+        height as usize,
+        ChannelCount::Rgba,
+    )
+}
+
+/// Encodes an RGB image, taking the dimensions from the image. Returns the total number of bytes
+/// written
+pub fn encode_rgb_image<W: Write + Seek>(writer: W, img: &RgbImage) -> Result<u64> {
+    let (width, height) = img.dimensions();
+    encode_dims(
+        writer,
+        img.as_raw(),
+        width as usize,
+        height as usize,
+        ChannelCount::Rgb,
+    )
+}
+
+/// Decodes an image as RGBA
+pub fn to_rgba_image<R: Read>(reader: R) -> Result<RgbaImage> {
+    let (data, width, height) = decode_converted(reader, ChannelCount::Rgba)?;
+    Ok(RgbaImage::from_raw(width, height, data).expect("Decoded buffer matches dimensions"))
+}
@@ -1,18 +1,307 @@
 use std::{
+    fmt,
     fs::File,
-    io::{BufReader, BufWriter, Read, Result, Seek, SeekFrom, Write},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
-type Rgba = [u8; 4];
+mod qoi16;
+pub use qoi16::{decode16, decode16_auto, encode16_to_vec, is_qoi16, Rgba16};
+
+mod metadata;
+pub use metadata::{encode_with_metadata, read_metadata};
+
+mod anim;
+pub use anim::{is_animation, AnimDecoder, AnimEncoder, Frame};
+
+#[cfg(feature = "image")]
+mod image_ext;
+#[cfg(feature = "image")]
+pub use image_ext::{encode_image, encode_rgb_image, from_dynamic_image, to_rgba_image};
+
+#[cfg(feature = "png")]
+mod png_ext;
+#[cfg(feature = "png")]
+pub use png_ext::{png_to_qoi, qoi_to_png};
+
+#[cfg(feature = "mmap")]
+mod mmap_ext;
+#[cfg(feature = "mmap")]
+pub use mmap_ext::read_from_file_mmap;
+
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "rayon")]
+pub use parallel::{decode_parallel, encode_parallel, is_parallel_container};
+
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "wasm")]
+pub use wasm::{decode_to_rgba, DecodedImage};
+
+#[cfg(feature = "egui")]
+mod egui_ext;
+#[cfg(feature = "egui")]
+pub use egui_ext::decode_color_image;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[cfg(feature = "tokio")]
+mod tokio_ext;
+#[cfg(feature = "tokio")]
+pub use tokio_ext::{decode_async, encode_async};
+
+/// A single pixel as red, green, blue, alpha
+pub type Rgba = [u8; 4];
+
+/// Errors which may occur while encoding or decoding an image
+#[derive(Debug)]
+pub enum QoiError {
+    /// The stream does not begin with the QOI magic number
+    BadMagic([u8; 4]),
+    /// The stream was written with an unsupported header layout. Version 0 is the original unversioned layout
+    UnsupportedVersion(u8),
+    /// There is no pixel data to encode
+    EmptyImage,
+    /// The width or height is zero
+    ZeroDimension,
+    /// The header declares a channel count other than 1, 3 or 4
+    BadChannelCount(u8),
+    /// The header declares an unknown colorspace
+    BadColorspace(u8),
+    /// The header sets flag bits this version doesn't understand
+    UnsupportedFlags(u8),
+    /// A run continues past the end of a row in a stream which resets the previous pixel at the
+    /// start of every row
+    RunAcrossRows,
+    /// The last run continues past the last pixel of the image. This isn't a `SizeMismatch`: the
+    /// stream can occupy exactly its declared length and still hold such a run, so there are no
+    /// differing byte counts to report
+    RunPastEnd,
+    /// The CRC32 stored after the compressed data doesn't match the data
+    ChecksumMismatch { stored: u32, computed: u32 },
+    /// The stream ended before the image was complete
+    UnexpectedEof,
+    /// The data length is not a multiple of the channel count
+    DataNotMultipleOfChannels,
+    /// The data length is not a multiple of the row length, `width * channels`
+    WidthDoesNotDivideData,
+    /// The row stride is shorter than a row of pixels
+    StrideTooSmall { stride: usize, row_len: usize },
+    /// The data is too short for the given dimensions
+    DataTooShort { required: usize, available: usize },
+    /// A multi-band or animation container is inconsistent with the images it holds
+    InvalidContainer,
+    /// The width or height is larger than `MAX_DIMENSION`
+    DimensionTooLarge,
+    /// The source image uses a bit depth other than 8 bits per channel
+    UnsupportedBitDepth(u8),
+    /// The image data contains a byte which is not a known opcode
+    InvalidOpcode(u8),
+    /// `QOI_INDEX` refers to a slot of the color index which no earlier pixel has filled. Only
+    /// reported by `decode_strict`
+    UnwrittenIndex(u8),
+    /// The opcode at byte `offset` of the stream isn't the one this crate's encoder would have
+    /// chosen. Only reported by `decode_strict`
+    NonCanonical { offset: usize },
+    /// The image data is not followed by the zeroed padding
+    BadPadding,
+    /// The compressed data length in the header does not match the data actually encoded. A
+    /// length too short for any stream is reported by `read_header`, with the shortest possible
+    /// length as `actual`
+    SizeMismatch { declared: u32, actual: usize },
+    /// The compressed data takes `len` bytes, more than the header's 32-bit size field can record
+    CompressedTooLarge { len: usize },
+    /// The decoded image would exceed the allowed memory limit
+    TooLarge { required: usize, limit: usize },
+    /// The output buffer cannot hold the decoded image
+    BufferTooSmall { required: usize, available: usize },
+    /// The requested region extends past the edge of the image
+    RegionOutOfBounds,
+    /// A metadata trailer is malformed, or an entry is too long to store
+    InvalidMetadata,
+    /// A `LineEncoder` was given a different number of rows than the height it was created with
+    RowCountMismatch { expected: u32, written: u32 },
+    /// A frame added to an `AnimEncoder` isn't the size of the animation, in bytes
+    FrameSizeMismatch { expected: usize, actual: usize },
+    /// The image is stored with `expected` channels but was decoded as `requested`. Reported by
+    /// every decode function taking a channel count except `decode_converted`, which converts
+    ChannelMismatch {
+        expected: ChannelCount,
+        requested: ChannelCount,
+    },
+    /// Any other I/O error from the underlying reader or writer
+    Io(io::Error),
+}
+
+impl From<io::Error> for QoiError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => QoiError::UnexpectedEof,
+            _ => QoiError::Io(err),
+        }
+    }
+}
+
+impl fmt::Display for QoiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QoiError::BadMagic(magic) => write!(
+                f,
+                "bad magic number \"{}\", expected \"{}\"",
+                magic.escape_ascii(),
+                MAGIC.escape_ascii()
+            ),
+            QoiError::UnsupportedVersion(version) => {
+                write!(f, "unsupported header version {}", version)
+            }
+            QoiError::EmptyImage => write!(f, "no pixel data to encode"),
+            QoiError::ZeroDimension => write!(f, "the width or height is zero"),
+            QoiError::BadChannelCount(channels) => write!(f, "bad channel count {}", channels),
+            QoiError::BadColorspace(colorspace) => write!(f, "unknown colorspace {}", colorspace),
+            QoiError::UnsupportedFlags(flags) => {
+                write!(f, "unsupported header flags {:#010b}", flags)
+            }
+            QoiError::RunAcrossRows => write!(f, "a run continues past the end of a row"),
+            QoiError::RunPastEnd => write!(f, "the last run continues past the end of the image"),
+            QoiError::ChecksumMismatch { stored, computed } => write!(
+                f,
+                "CRC mismatch: stored {:#010x}, computed {:#010x}",
+                stored, computed
+            ),
+            QoiError::UnexpectedEof => write!(f, "the stream ended before the image was complete"),
+            QoiError::DataNotMultipleOfChannels => {
+                write!(f, "the data length is not a multiple of the channel count")
+            }
+            QoiError::WidthDoesNotDivideData => {
+                write!(f, "the data length is not a multiple of the row length")
+            }
+            QoiError::StrideTooSmall { stride, row_len } => write!(
+                f,
+                "stride of {} bytes is shorter than a row of {} bytes",
+                stride, row_len
+            ),
+            QoiError::DataTooShort {
+                required,
+                available,
+            } => write!(
+                f,
+                "data is too short: {} bytes required, {} available",
+                required, available
+            ),
+            QoiError::InvalidContainer => {
+                write!(f, "the container is inconsistent with its images")
+            }
+            QoiError::DimensionTooLarge => write!(
+                f,
+                "the width or height is larger than the maximum of {}",
+                MAX_DIMENSION
+            ),
+            QoiError::UnsupportedBitDepth(bits) => write!(f, "unsupported bit depth {}", bits),
+            QoiError::InvalidOpcode(opcode) => write!(f, "invalid opcode {:#04x}", opcode),
+            QoiError::UnwrittenIndex(slot) => {
+                write!(f, "index slot {} is used before it is written", slot)
+            }
+            QoiError::NonCanonical { offset } => {
+                write!(f, "non-canonical opcode at byte {}", offset)
+            }
+            QoiError::BadPadding => write!(f, "the image data is not followed by zeroed padding"),
+            QoiError::SizeMismatch { declared, actual } => write!(
+                f,
+                "compressed size mismatch: the header declares {} bytes, but the data takes {}",
+                declared, actual
+            ),
+            QoiError::CompressedTooLarge { len } => write!(
+                f,
+                "the compressed data takes {} bytes, more than the header can record",
+                len
+            ),
+            QoiError::TooLarge { required, limit } => write!(
+                f,
+                "the decoded image needs {} bytes, more than the limit of {}",
+                required, limit
+            ),
+            QoiError::BufferTooSmall {
+                required,
+                available,
+            } => write!(
+                f,
+                "output buffer is too small: {} bytes required, {} available",
+                required, available
+            ),
+            QoiError::RegionOutOfBounds => {
+                write!(f, "the region extends past the edge of the image")
+            }
+            QoiError::InvalidMetadata => write!(f, "invalid metadata"),
+            QoiError::RowCountMismatch { expected, written } => {
+                write!(
+                    f,
+                    "expected {} rows, but {} were written",
+                    expected, written
+                )
+            }
+            QoiError::FrameSizeMismatch { expected, actual } => write!(
+                f,
+                "frame is {} bytes, but the animation's frames are {}",
+                actual, expected
+            ),
+            QoiError::ChannelMismatch {
+                expected,
+                requested,
+            } => write!(
+                f,
+                "the image has {} channels, but {} were requested",
+                expected.bytes_per_pixel(),
+                requested.bytes_per_pixel()
+            ),
+            QoiError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for QoiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QoiError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T, E = QoiError> = std::result::Result<T, E>;
+
+/// The largest decoded image, in bytes, which `decode` will allocate (1 GiB)
+pub const DEFAULT_DECODE_LIMIT: usize = 1 << 30;
+
+/// The largest width or height which is encoded or decoded. At this size a row of RGBA pixels
+/// still fits in a `u32` and the pixel count of any image in a `u64`
+pub const MAX_DIMENSION: u32 = 1 << 24;
 
 const COLOR_LUT_SIZE: usize = 64;
+// QOI_INDEX holds a 6 bit slot, and reducing a hash to a slot must stay a cheap mask
+const _: () = assert!(COLOR_LUT_SIZE.is_power_of_two() && COLOR_LUT_SIZE <= 64);
 /// The pixel decoded if the first pixel is an RLE command
 const DEFAULT_PREV_PIXEL: Rgba = [0, 0, 0, 0xFF];
 const MAX_RUN_LENGTH: u32 = 0x2020;
 const MAX_RUN_8_LENGTH: u32 = 33;
-const MAGIC: &[u8; 4] = b"qoif";
+const MAGIC: &[u8; 4] = b"qoiv";
+/// Magic number of the original header layout, which had no version or channel count
+const LEGACY_MAGIC: &[u8; 4] = b"qoif";
+/// Version of the header layout following the magic number
+const FORMAT_VERSION: u8 = 4;
+/// Byte offset of the compressed size field within the header
+const SIZE_FIELD_OFFSET: usize = 16;
+const HEADER_SIZE: usize = SIZE_FIELD_OFFSET + 4;
+
+/// Header flag: a CRC32 of the compressed data follows it
+const FLAG_CRC: u8 = 0b00000001;
+/// Header flag: the previous pixel is reset at the start of every row
+const FLAG_RESET_ROWS: u8 = 0b00000010;
+const CRC_SIZE: usize = 4;
 
+/// Zero bytes following the opcodes. This is the draft's 4-byte padding, not the 8-byte
+/// `00 00 00 00 00 00 00 01` end marker of QOI 1.0
 const QOI_PADDING: usize = 4;
 const QOI_INDEX: u8 = 0b00000000; // 00xxxxxx
 const QOI_RUN_8: u8 = 0b01000000; // 010xxxxx
@@ -34,299 +323,2736 @@ pub fn write_to_file(
 ) -> Result<()> {
     let file = File::create(path)?;
     let mut writer = BufWriter::new(file);
+    // BufWriter writes out its buffer before seeking, so the body reaches the file before the
+    // size is written back into the header. The flush then writes the size itself
     encode(&mut writer, data, width, channels)?;
-    writer.flush()
+    Ok(writer.flush()?)
 }
 
 pub fn read_from_file(
     path: impl AsRef<Path>,
     channels: ChannelCount,
-) -> Result<(Vec<u8>, u16, u16)> {
+) -> Result<(Vec<u8>, u32, u32)> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
     decode(reader, channels)
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(usize)]
 pub enum ChannelCount {
+    Gray = 1,
     Rgb = 3,
     Rgba = 4,
 }
 
-fn color_hash([r, g, b, a]: Rgba) -> u8 {
+impl ChannelCount {
+    /// Number of bytes each pixel occupies in 8-bit image data
+    pub fn bytes_per_pixel(self) -> usize {
+        self as usize
+    }
+}
+
+impl TryFrom<u8> for ChannelCount {
+    type Error = QoiError;
+
+    /// Converts a channel count as stored in the header
+    fn try_from(channels: u8) -> Result<Self> {
+        match channels {
+            1 => Ok(ChannelCount::Gray),
+            3 => Ok(ChannelCount::Rgb),
+            4 => Ok(ChannelCount::Rgba),
+            other => Err(QoiError::BadChannelCount(other)),
+        }
+    }
+}
+
+/// How the pixel values should be interpreted. This is informational only, and doesn't affect
+/// how pixels are encoded
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Colorspace {
+    /// sRGB color channels with linear alpha
+    #[default]
+    Srgb = 0,
+    /// All channels linear
+    Linear = 1,
+}
+
+/// Byte order of the color channels in decoded output. The stream itself always stores RGBA;
+/// this only affects the layout of the returned bytes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PixelOrder {
+    #[default]
+    Rgba,
+    /// Red and blue swapped, as wanted by many GPU and Windows APIs. With 3 channels this is BGR
+    Bgra,
+}
+
+/// Hash of a pixel, which modulo the index size gives its position in the color index.
+///
+/// This is the XOR hash from the original draft of the format, not the weighted
+/// `r * 3 + g * 5 + b * 7 + a * 11` hash of the QOI 1.0 specification. The two are not
+/// interchangeable, see the compatibility notes in the README
+pub fn color_hash([r, g, b, a]: Rgba) -> u8 {
     r ^ g ^ b ^ a
 }
 
-fn subtract_pixels([rx, gx, bx, ax]: Rgba, [ry, gy, by, ay]: Rgba) -> [i32; 4] {
-    return [
+/// The most recently seen color for each slot of the index, as kept by the encoder and decoder.
+/// Every slot starts out as transparent black
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorIndex([Rgba; COLOR_LUT_SIZE]);
+
+impl ColorIndex {
+    pub fn new() -> Self {
+        Self([[0; 4]; COLOR_LUT_SIZE])
+    }
+
+    /// The slot `px` is stored in
+    pub fn slot(px: Rgba) -> u8 {
+        color_hash(px) % COLOR_LUT_SIZE as u8
+    }
+
+    /// Stores `px` in its slot
+    pub fn insert(&mut self, px: Rgba) {
+        self.0[Self::slot(px) as usize] = px;
+    }
+
+    /// The color in the slot for `hash`, which is reduced modulo the index size, so either a hash
+    /// or a slot may be given
+    pub fn get(&self, hash: u8) -> Rgba {
+        self.0[(hash % COLOR_LUT_SIZE as u8) as usize]
+    }
+
+    /// Empties every slot, as at the start of a stream
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for ColorIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-channel difference `x - y` between two pixels
+pub fn subtract_pixels([rx, gx, bx, ax]: Rgba, [ry, gy, by, ay]: Rgba) -> [i32; 4] {
+    [
         rx as i32 - ry as i32,
         gx as i32 - gy as i32,
         bx as i32 - by as i32,
         ax as i32 - ay as i32,
-    ];
+    ]
 }
 
+/// Encodes the image, returning the total number of bytes written. The writer is left at the end
+/// of the stream.
+///
+/// Like every function here taking a writer or reader by value, it also accepts `&mut W`, which
+/// keeps the writer for embedding the stream in a larger file:
+/// ```
+/// use qoi_rs::{encode, ChannelCount};
+/// use std::io::{Cursor, Write};
+///
+/// let mut file = Cursor::new(Vec::new());
+/// file.write_all(b"before")?;
+/// let len = encode(&mut file, &[255, 0, 0], 1, ChannelCount::Rgb)?;
+/// file.write_all(b"after")?;
+/// assert_eq!(file.get_ref().len() as u64, 6 + len + 5);
+/// # Ok::<(), qoi_rs::QoiError>(())
+/// ```
 pub fn encode<W: Write + Seek>(
-    mut writer: W,
+    writer: W,
     data: &[u8],
     width: usize,
     channels: ChannelCount,
-) -> Result<()> {
-    let (width, height, total_pixels) = verify_and_calculate_dims(data, width, channels);
+) -> Result<u64> {
+    encode_with_colorspace(writer, data, width, channels, Colorspace::default())
+}
 
-    let size_field_offset = encode_header(&mut writer, width, height)?;
+/// Encodes the image followed by a CRC32 of the compressed data, which every decode function
+/// except `decode_pixels` and `Decoder` verifies. Returns the total number of bytes written
+pub fn encode_with_crc<W: Write + Seek>(
+    writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+) -> Result<u64> {
+    encode_with_options(
+        writer,
+        data,
+        width,
+        channels,
+        &EncodeOptions::new().crc(true),
+    )
+}
 
-    let mut image_data_len: usize = 0; // Length of image bytes written in bytes
+/// Encodes tightly packed RGBA data. Equivalent to `encode` with [`ChannelCount::Rgba`]
+pub fn encode_rgba<W: Write + Seek>(writer: W, data: &[u8], width: usize) -> Result<u64> {
+    encode(writer, data, width, ChannelCount::Rgba)
+}
 
-    let mut run: u32 = 0; // Run length encoding run length
-    let mut px_prev = DEFAULT_PREV_PIXEL; // Previous pixel
-    let mut px = px_prev; // Current pixel
-    let mut index = [[0; 4]; COLOR_LUT_SIZE];
+/// Encodes RGBA pixels, e.g. a `Vec<Rgba>` from another image library, without copying them into
+/// a byte buffer first. Returns the total number of bytes written
+pub fn encode_rgba_pixels<W: Write + Seek>(
+    writer: W,
+    pixels: &[Rgba],
+    width: usize,
+) -> Result<u64> {
+    encode(writer, pixels.as_flattened(), width, ChannelCount::Rgba)
+}
 
-    for (pixel_idx, pixel_data) in data.chunks_exact(channels as usize).enumerate() {
-        // Copy pixel data
-        px[..channels as usize].copy_from_slice(pixel_data);
+/// Encodes tightly packed RGB data. Equivalent to `encode` with [`ChannelCount::Rgb`]
+pub fn encode_rgb<W: Write + Seek>(writer: W, data: &[u8], width: usize) -> Result<u64> {
+    encode(writer, data, width, ChannelCount::Rgb)
+}
 
-        // Pixel matches the previous one, increase run length
-        let pixel_matches_last = px == px_prev;
-        if pixel_matches_last {
-            run += 1;
-        }
-
-        // There is a run, and we've reached the max run length, the last pixel doesn't match, or we've reached the very last pixel (so we must dump any current run).
-        if run > 0
-            && (run == MAX_RUN_LENGTH || !pixel_matches_last || pixel_idx + 1 == total_pixels)
-        {
-            if run < MAX_RUN_8_LENGTH {
-                // Write a short run length
-                run -= 1;
-                let message: u8 = QOI_RUN_8 | run as u8;
-                image_data_len += writer.write(&[message])?;
-            } else {
-                // Write a long run length
-                run -= MAX_RUN_8_LENGTH;
-                image_data_len += writer.write(&[QOI_RUN_16 | (run >> 8) as u8, run as u8])?;
-            }
-            run = 0;
+/// Encodes the image, recording `colorspace` in the header. Returns the total number of bytes written
+pub fn encode_with_colorspace<W: Write + Seek>(
+    writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+    colorspace: Colorspace,
+) -> Result<u64> {
+    encode_with_options(
+        writer,
+        data,
+        width,
+        channels,
+        &EncodeOptions::new().colorspace(colorspace),
+    )
+}
+
+/// Settings for [`encode_with_options`]. The defaults match `encode`
+#[derive(Copy, Clone, Debug)]
+pub struct EncodeOptions {
+    colorspace: Colorspace,
+    crc: bool,
+    use_index: bool,
+    reset_rows: bool,
+    flip_vertical: bool,
+}
+
+impl EncodeOptions {
+    pub fn new() -> Self {
+        Self {
+            colorspace: Colorspace::default(),
+            crc: false,
+            use_index: true,
+            reset_rows: false,
+            flip_vertical: false,
         }
+    }
 
-        if !pixel_matches_last {
-            let index_pos = color_hash(px) % 64;
+    /// The colorspace recorded in the header
+    pub fn colorspace(mut self, colorspace: Colorspace) -> Self {
+        self.colorspace = colorspace;
+        self
+    }
 
-            if px == index[index_pos as usize] {
-                image_data_len += writer.write(&[QOI_INDEX | index_pos])?;
-            } else {
-                index[index_pos as usize] = px;
-                let diff = subtract_pixels(px, px_prev);
-                let [vr, vg, vb, va] = diff;
-
-                let within_small_diff = diff.into_iter().all(|v| v > -16 && v < 17);
-
-                if within_small_diff {
-                    // Use difference encoding
-                    if va == 0 && vr > -2 && vr < 3 && vg > -2 && vg < 3 && vb > -2 && vb < 3 {
-                        // Use 2-bit difference encoding
-                        image_data_len += writer.write(&[
-                            QOI_DIFF_8 | (((vr + 1) << 4) | (vg + 1) << 2 | (vb + 1)) as u8
-                        ])?;
-                    } else if va == 0
-                        && vr > -16
-                        && vr < 17
-                        && vg > -8
-                        && vg < 9
-                        && vb > -8
-                        && vb < 9
-                    {
-                        // Use 5 or 4-bit difference encoding
-                        image_data_len += writer.write(&[
-                            QOI_DIFF_16 | (vr + 15) as u8,
-                            (((vg + 7) << 4) | (vb + 7)) as u8,
-                        ])?;
-                    } else {
-                        // Use 5-bit difference encoding
-                        image_data_len += writer.write(&[
-                            QOI_DIFF_24 | ((vr + 15) >> 1) as u8,
-                            (((vr + 15) << 7) | ((vg + 15) << 2) | ((vb + 15) >> 3)) as u8,
-                            (((vb + 15) << 5) | (va + 15)) as u8,
-                        ])?;
-                    }
-                } else {
-                    // Encode an entire pixel (but only the differing components)
-                    let gate = |v: i32, x: u8| if v != 0 { x } else { 0 };
-
-                    image_data_len += writer.write(&[QOI_COLOR
-                        | gate(vr, 8)
-                        | gate(vg, 4)
-                        | gate(vb, 2)
-                        | gate(va, 1)])?;
-
-                    if vr != 0 {
-                        image_data_len += writer.write(&[px[0]])?;
-                    }
-                    if vg != 0 {
-                        image_data_len += writer.write(&[px[1]])?;
-                    }
-                    if vb != 0 {
-                        image_data_len += writer.write(&[px[2]])?;
-                    }
-                    if va != 0 {
-                        image_data_len += writer.write(&[px[3]])?;
-                    }
-                }
-            }
+    /// Whether to append a CRC32 of the compressed data, as `encode_with_crc` does
+    pub fn crc(mut self, crc: bool) -> Self {
+        self.crc = crc;
+        self
+    }
+
+    /// Whether to look pixels up in the color index. Disabling it skips a hash and a table lookup
+    /// per pixel, at the cost of a larger file. The output still decodes with any decoder
+    pub fn use_index(mut self, use_index: bool) -> Self {
+        self.use_index = use_index;
+        self
+    }
+
+    /// Whether to end any run and reset the previous pixel to opaque black at the start of every
+    /// row, so that no opcode refers to a pixel of an earlier row except through the color index.
+    /// This can help when neighbouring rows are unrelated, e.g. a sheet of small sprites, but
+    /// costs a little on most images. The choice is recorded in the header
+    pub fn reset_rows(mut self, reset_rows: bool) -> Self {
+        self.reset_rows = reset_rows;
+        self
+    }
+
+    /// Whether the data is stored bottom row first, as read back from an OpenGL framebuffer. The
+    /// rows are encoded in reverse, so the file is top-down like any other without the data being
+    /// flipped in memory first
+    pub fn flip_vertical(mut self, flip_vertical: bool) -> Self {
+        self.flip_vertical = flip_vertical;
+        self
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.crc {
+            flags |= FLAG_CRC;
+        }
+        if self.reset_rows {
+            flags |= FLAG_RESET_ROWS;
         }
+        flags
+    }
+}
 
-        px_prev = px;
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    // Padding
-    image_data_len += writer.write(&[0; QOI_PADDING])?;
+/// Encodes the image with the given options. Returns the total number of bytes written
+pub fn encode_with_options<W: Write + Seek>(
+    writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+    options: &EncodeOptions,
+) -> Result<u64> {
+    let (width, height, _) = verify_and_calculate_dims(data, width, channels)?;
 
-    // Seek and write the length to the header
-    encode_size(writer, image_data_len as u32, size_field_offset)
+    if options.flip_vertical {
+        let row_len = width as usize * channels.bytes_per_pixel();
+        let rows = data.chunks_exact(row_len).rev();
+        return encode_seekable(writer, rows, width, height, channels, options);
+    }
+
+    let rows = std::iter::once(data);
+    encode_seekable(writer, rows, width, height, channels, options)
 }
 
-/// Returns (width, height, total_pixels) and verifies that the image dimensions and channel count match the data
-#[track_caller]
-pub fn verify_and_calculate_dims(
+/// Encodes the image, calling `progress(pixels_done, total_pixels)` before each row and once more
+/// when finished. The output is identical to `encode`. Returns the total number of bytes written
+pub fn encode_with_progress<W: Write + Seek, F: FnMut(usize, usize)>(
+    writer: W,
     data: &[u8],
     width: usize,
     channels: ChannelCount,
-) -> (u16, u16, usize) {
-    // Check that the width and data length match up
-    assert!(
-        data.len() % (channels as usize) == 0,
-        "Pixel count must be a multiple of channel count ({}).",
-        channels as usize
-    );
-    assert!(
-        data.len() % width == 0,
-        "Pixel count must be a multiple of width"
-    );
-    let height = data.len() / (width as usize * channels as usize);
+    mut progress: F,
+) -> Result<u64> {
+    let (width, height, total_pixels) = verify_and_calculate_dims(data, width, channels)?;
+    let row_len = width as usize * channels as usize;
+
+    let rows = data.chunks(row_len).enumerate().map(|(y, row)| {
+        progress(y * width as usize, total_pixels);
+        row
+    });
+    let written = encode_seekable(
+        writer,
+        rows,
+        width,
+        height,
+        channels,
+        &EncodeOptions::default(),
+    )?;
 
-    let height: u16 = height.try_into().expect("Image height > 2^16");
-    let width: u16 = width.try_into().expect("Image width > 2^16");
-    let total_pixels = data.len() / 3;
+    progress(total_pixels, total_pixels);
 
-    (width, height, total_pixels)
+    Ok(written)
 }
 
-/// Returns the offset at which the file size will be written
-fn encode_header<W: Write + Seek>(mut writer: W, width: u16, height: u16) -> Result<u64> {
-    writer.write(MAGIC)?;
-    writer.write(&width.to_le_bytes())?;
-    writer.write(&height.to_le_bytes())?;
-    let offset = writer.seek(SeekFrom::Current(0))?;
-    writer.write(&0u32.to_le_bytes())?;
-    Ok(offset)
+/// Encodes an RGBA image from separate color and alpha planes, without interleaving them first.
+/// `color` holds `channels` bytes per pixel and `alpha` one byte per pixel; with
+/// [`ChannelCount::Rgba`] the alpha in `color` is ignored. Returns the total number of bytes written
+pub fn encode_planar<W: Write + Seek>(
+    mut writer: W,
+    color: &[u8],
+    alpha: &[u8],
+    width: usize,
+    channels: ChannelCount,
+) -> Result<u64> {
+    let (width, height, total_pixels) = verify_and_calculate_dims(color, width, channels)?;
+    if alpha.len() < total_pixels {
+        return Err(QoiError::DataTooShort {
+            required: total_pixels,
+            available: alpha.len(),
+        });
+    }
+    let alpha = &alpha[..total_pixels];
+
+    let size_field_offset = encode_header(
+        &mut writer,
+        width,
+        height,
+        ChannelCount::Rgba,
+        Colorspace::default(),
+        0,
+    )?;
+
+    let image_data_len = match channels {
+        ChannelCount::Gray => encode_planar_pixels::<_, 1>(&mut writer, color, alpha),
+        ChannelCount::Rgb => encode_planar_pixels::<_, 3>(&mut writer, color, alpha),
+        ChannelCount::Rgba => encode_planar_pixels::<_, 4>(&mut writer, color, alpha),
+    }?;
+
+    // Seek and write the length to the header
+    encode_size(writer, size_field(image_data_len)?, size_field_offset)?;
+
+    Ok((HEADER_SIZE + image_data_len) as u64)
 }
 
-fn encode_size<W: Write + Seek>(mut writer: W, size: u32, offset: u64) -> Result<()> {
-    writer.seek(SeekFrom::Start(offset))?;
-    writer.write(&size.to_le_bytes())?;
-    Ok(())
+/// Like `encode_pixels_n`, taking the alpha of each pixel from `alpha`
+fn encode_planar_pixels<W: Write, const N: usize>(
+    writer: W,
+    color: &[u8],
+    alpha: &[u8],
+) -> io::Result<usize> {
+    let mut out = OutputBuffer::new(writer);
+    let mut state = EncoderState::new(true);
+
+    let (pixels, _) = color.as_chunks::<N>();
+    for (pixel_data, &a) in pixels.iter().zip(alpha) {
+        let mut px = expand_pixel(pixel_data);
+        px[3] = a;
+        state.push(px, &mut out)?;
+    }
+
+    // Dump any current run
+    state.flush_run(&mut out)?;
+
+    // Padding
+    out.push(&[0; QOI_PADDING])?;
+
+    out.finish()
 }
 
-/// Returns (width, height, compressed data size) for the given reader
-fn decode_header<R: Read>(mut reader: R) -> Result<(u16, u16, u32)> {
-    let mut short_buf = [0u8; 2];
-    let mut long_buf = [0u8; 4];
+/// Encodes the first `width * height` pixels yielded by `pixels`, so generated images never need
+/// to be collected into a buffer. With [`ChannelCount::Rgb`] the alpha of each pixel is ignored,
+/// and with [`ChannelCount::Gray`] only the red channel is kept. Returns the total number of bytes
+/// written
+pub fn encode_from_iter<W: Write + Seek, I: Iterator<Item = Rgba>>(
+    mut writer: W,
+    pixels: I,
+    width: usize,
+    height: usize,
+    channels: ChannelCount,
+) -> Result<u64> {
+    if width == 0 || height == 0 {
+        return Err(QoiError::ZeroDimension);
+    }
+    let total_pixels = width
+        .checked_mul(height)
+        .ok_or(QoiError::DimensionTooLarge)?;
 
-    // Check magic
-    reader.read_exact(&mut long_buf)?;
-    assert_eq!(&long_buf, MAGIC, "Missing magic number");
+    let width = dimension_field(width)?;
+    let height = dimension_field(height)?;
 
-    // Read width, height
-    reader.read_exact(&mut short_buf)?;
-    let width = u16::from_le_bytes(short_buf);
+    let size_field_offset = encode_header(
+        &mut writer,
+        width,
+        height,
+        channels,
+        Colorspace::default(),
+        0,
+    )?;
 
-    reader.read_exact(&mut short_buf)?;
-    let height = u16::from_le_bytes(short_buf);
+    let mut out = OutputBuffer::new(&mut writer);
+    let mut state = EncoderState::new(true);
 
-    assert_ne!(width, 0);
-    assert_ne!(height, 0);
+    let mut count = 0;
+    for [r, g, b, a] in pixels.take(total_pixels) {
+        // Reduce to what the header's channel count can store, so the image decodes as given
+        let px = match channels {
+            ChannelCount::Gray => [r, r, r, 0xFF],
+            ChannelCount::Rgb => [r, g, b, 0xFF],
+            ChannelCount::Rgba => [r, g, b, a],
+        };
+        state.push(px, &mut out)?;
+        count += 1;
+    }
 
-    // Read compressed size
-    reader.read_exact(&mut long_buf)?;
+    if count < total_pixels {
+        return Err(QoiError::DataTooShort {
+            required: total_pixels,
+            available: count,
+        });
+    }
 
-    let compressed_data_len = u32::from_le_bytes(long_buf);
+    // Dump any current run
+    state.flush_run(&mut out)?;
+
+    // Padding
+    out.push(&[0; QOI_PADDING])?;
+
+    let image_data_len = out.finish()?;
 
-    Ok((width, height, compressed_data_len))
+    // Seek and write the length to the header
+    encode_size(writer, size_field(image_data_len)?, size_field_offset)?;
+
+    Ok((HEADER_SIZE + image_data_len) as u64)
 }
 
-/// Returns (image data, width, height)
-pub fn decode<R: Read>(mut reader: R, channels: ChannelCount) -> Result<(Vec<u8>, u16, u16)> {
-    let (width, height, _) = decode_header(&mut reader)?;
+/// Encodes the first `width * height` pixels of `data`, which may be longer. Returns the total
+/// number of bytes written
+pub fn encode_dims<W: Write + Seek>(
+    writer: W,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    channels: ChannelCount,
+) -> Result<u64> {
+    let stride = width
+        .checked_mul(channels as usize)
+        .ok_or(QoiError::DimensionTooLarge)?;
+    encode_strided(writer, data, width, height, stride, channels)
+}
+
+/// Encodes an image whose rows begin `stride` bytes apart. Only the first `width * channels`
+/// bytes of each row are encoded, so `stride >= width * channels` is required. Returns the total
+/// number of bytes written
+pub fn encode_strided<W: Write + Seek>(
+    writer: W,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    channels: ChannelCount,
+) -> Result<u64> {
+    if width == 0 || height == 0 {
+        return Err(QoiError::ZeroDimension);
+    }
+
+    let width_field = dimension_field(width)?;
+    let height_field = dimension_field(height)?;
+
+    let row_len = width * channels as usize;
+    if stride < row_len {
+        return Err(QoiError::StrideTooSmall { stride, row_len });
+    }
+
+    // The final row doesn't need to be padded out to the full stride. Saturates, since no slice
+    // is that long anyway
+    let required = (height - 1).saturating_mul(stride).saturating_add(row_len);
+    if data.len() < required {
+        return Err(QoiError::DataTooShort {
+            required,
+            available: data.len(),
+        });
+    }
 
-    let mut run: u32 = 0; // Run length encoding run length
-    let mut px = DEFAULT_PREV_PIXEL; // Previous pixel
-    let mut index = [[0; 4]; COLOR_LUT_SIZE];
+    let rows = data.chunks(stride).take(height).map(|row| &row[..row_len]);
 
-    let total_pixels = width as usize * height as usize;
-    let uncompressed_len = total_pixels * channels as usize; // Uncompressed image data length
+    encode_seekable(
+        writer,
+        rows,
+        width_field,
+        height_field,
+        channels,
+        &EncodeOptions::default(),
+    )
+}
 
-    let mut out_buf = Vec::with_capacity(uncompressed_len);
+/// Writes the header, the given rows of pixels, the CRC if `options` asks for one, and then seeks
+/// back to fill in the size. Returns the total number of bytes written
+fn encode_seekable<'a, W: Write + Seek>(
+    mut writer: W,
+    rows: impl Iterator<Item = &'a [u8]>,
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    options: &EncodeOptions,
+) -> Result<u64> {
+    let size_field_offset = encode_header(
+        &mut writer,
+        width,
+        height,
+        channels,
+        options.colorspace,
+        options.flags(),
+    )?;
 
-    let mut read_byte = || -> Result<u8> {
-        let mut buf = [0u8];
-        reader.read_exact(&mut buf)?;
-        Ok(buf[0])
+    let (image_data_len, crc) = if options.crc {
+        let mut crc_writer = CrcWriter::new(&mut writer);
+        let len = encode_pixels(&mut crc_writer, rows, width, channels, options)?;
+        (len, Some(crc_writer.crc))
+    } else {
+        let len = encode_pixels(&mut writer, rows, width, channels, options)?;
+        (len, None)
     };
 
-    while out_buf.len() < uncompressed_len {
-        if run > 0 {
-            run -= 1;
-        } else {
-            let b1 = read_byte()?;
-
-            if (b1 & QOI_MASK_2) == QOI_INDEX {
-                px = index[(b1 ^ QOI_INDEX) as usize];
-            } else if (b1 & QOI_MASK_3) == QOI_RUN_8 {
-                run = (b1 & 0x1f) as u32;
-            } else if (b1 & QOI_MASK_3) == QOI_RUN_16 {
-                let b2 = read_byte()?;
-                run = ((((b1 & 0x1f) as u32) << 8) | (b2 as u32)) + 32;
-            } else if (b1 & QOI_MASK_2) == QOI_DIFF_8 {
-                px[0] += ((b1 >> 4) & 0x03) - 1;
-                px[1] += ((b1 >> 2) & 0x03) - 1;
-                px[2] += (b1 & 0x03) - 1;
-            } else if (b1 & QOI_MASK_3) == QOI_DIFF_16 {
-                let b2 = read_byte()?;
-                px[0] += (b1 & 0x1f) - 15;
-                px[1] += (b2 >> 4) - 7;
-                px[2] += (b2 & 0x0f) - 7;
-            } else if (b1 & QOI_MASK_4) == QOI_DIFF_24 {
-                let b2 = read_byte()?;
-                let b3 = read_byte()?;
-                px[0] += (((b1 & 0x0f) << 1) | (b2 >> 7)) - 15;
-                px[1] += ((b2 & 0x7c) >> 2) - 15;
-                px[2] += (((b2 & 0x03) << 3) | ((b3 & 0xe0) >> 5)) - 15;
-                px[3] += (b3 & 0x1f) - 15;
-            } else if (b1 & QOI_MASK_4) == QOI_COLOR {
-                if b1 & 8 != 0 {
-                    px[0] = read_byte()?;
-                }
-                if b1 & 4 != 0 {
-                    px[1] = read_byte()?;
-                }
-                if b1 & 2 != 0 {
-                    px[2] = read_byte()?;
-                }
-                if b1 & 1 != 0 {
-                    px[3] = read_byte()?;
-                }
-            }
+    let mut total_len = HEADER_SIZE + image_data_len;
+    if let Some(crc) = crc {
+        writer.write_all(&crc.to_le_bytes())?;
+        total_len += CRC_SIZE;
+    }
+
+    // Seek and write the length to the header
+    encode_size(writer, size_field(image_data_len)?, size_field_offset)?;
+
+    Ok(total_len as u64)
+}
+
+/// Encodes the image into a new buffer, without requiring the writer to implement `Seek`
+pub fn encode_to_vec(data: &[u8], width: usize, channels: ChannelCount) -> Result<Vec<u8>> {
+    let (width, height, _) = verify_and_calculate_dims(data, width, channels)?;
+
+    let mut buf = Vec::with_capacity(max_encoded_size(width, height, channels));
+    write_header(
+        &mut buf,
+        width,
+        height,
+        channels,
+        Colorspace::default(),
+        0,
+        0,
+    )
+    .expect("Writing to a Vec cannot fail");
+
+    let image_data_len = encode_pixels(
+        &mut buf,
+        std::iter::once(data),
+        width,
+        channels,
+        &EncodeOptions::default(),
+    )
+    .expect("Writing to a Vec cannot fail");
+
+    // Backpatch the length in the header
+    buf[SIZE_FIELD_OFFSET..SIZE_FIELD_OFFSET + 4]
+        .copy_from_slice(&size_field(image_data_len)?.to_le_bytes());
+
+    Ok(buf)
+}
+
+/// Encodes the image into `out`, e.g. a statically allocated buffer, and returns the number of
+/// bytes used. [`max_encoded_size`] gives a size which always suffices, and a buffer smaller than
+/// [`min_encoded_size`] is refused before encoding anything. Encoding stops as soon as the output
+/// doesn't fit, failing with `BufferTooSmall`, whose `required` is then only a lower bound. The
+/// header is written last, so a buffer which was too small never starts with one
+pub fn encode_into_buffer(
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+    out: &mut [u8],
+) -> Result<usize> {
+    let (width, height, _) = verify_and_calculate_dims(data, width, channels)?;
+
+    let available = out.len();
+    let min_size = min_encoded_size(width, height);
+    if available < min_size {
+        return Err(QoiError::BufferTooSmall {
+            required: min_size,
+            available,
+        });
+    }
+    let (header, body) = out.split_at_mut(HEADER_SIZE);
 
-            index[(color_hash(px) % 64) as usize] = px;
+    let mut body_writer = SliceWriter::new(body);
+    let image_data_len = match encode_pixels(
+        &mut body_writer,
+        std::iter::once(data),
+        width,
+        channels,
+        &EncodeOptions::default(),
+    ) {
+        Ok(len) => len,
+        Err(_) => {
+            return Err(QoiError::BufferTooSmall {
+                required: HEADER_SIZE + body_writer.required,
+                available,
+            })
         }
+    };
 
-        match channels {
-            ChannelCount::Rgba => out_buf.extend_from_slice(&px),
-            ChannelCount::Rgb => out_buf.extend_from_slice(&px[..3]),
+    write_header(
+        header,
+        width,
+        height,
+        channels,
+        Colorspace::default(),
+        0,
+        size_field(image_data_len)?,
+    )
+    .expect("The header fits in the space left for it");
+
+    Ok(HEADER_SIZE + image_data_len)
+}
+
+/// Encodes an image handed over one row at a time, e.g. as a producer renders it, without the
+/// writer implementing `Seek`. The header records the compressed length, which isn't known until
+/// the last row, so the compressed data is held in memory and everything is written by
+/// [`LineEncoder::finish`]. The uncompressed image is never held
+pub struct LineEncoder<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    rows_written: u32,
+    state: EncoderState,
+    out: OutputBuffer<Vec<u8>>,
+}
+
+impl<W: Write> LineEncoder<W> {
+    pub fn new(writer: W, width: usize, height: usize, channels: ChannelCount) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(QoiError::ZeroDimension);
         }
+
+        Ok(Self {
+            writer,
+            width: dimension_field(width)?,
+            height: dimension_field(height)?,
+            channels,
+            rows_written: 0,
+            state: EncoderState::new(true),
+            out: OutputBuffer::new(Vec::new()),
+        })
     }
 
-    Ok((out_buf, width, height))
-}
\ No newline at end of file
+    /// Encodes the next row. Only the first `width * channels` bytes of `row` are used
+    pub fn write_line(&mut self, row: &[u8]) -> Result<()> {
+        if self.rows_written == self.height {
+            return Err(QoiError::RowCountMismatch {
+                expected: self.height,
+                written: self.rows_written + 1,
+            });
+        }
+
+        let row_len = self.width as usize * self.channels.bytes_per_pixel();
+        let row = row.get(..row_len).ok_or(QoiError::DataTooShort {
+            required: row_len,
+            available: row.len(),
+        })?;
+
+        let (state, out) = (&mut self.state, &mut self.out);
+        match self.channels {
+            ChannelCount::Gray => encode_row::<_, 1>(state, row, out),
+            ChannelCount::Rgb => encode_row::<_, 3>(state, row, out),
+            ChannelCount::Rgba => encode_row::<_, 4>(state, row, out),
+        }
+        .expect("Writing to a Vec cannot fail");
+        self.rows_written += 1;
+
+        Ok(())
+    }
+
+    /// Writes the header followed by the compressed rows. Fails if fewer rows were written than
+    /// the image's height, or with `CompressedTooLarge` if the rows took more bytes than the
+    /// header can record. Returns the total number of bytes written
+    pub fn finish(mut self) -> Result<u64> {
+        if self.rows_written != self.height {
+            return Err(QoiError::RowCountMismatch {
+                expected: self.height,
+                written: self.rows_written,
+            });
+        }
+
+        // Dump any current run, then the padding
+        self.state
+            .flush_run(&mut self.out)
+            .and_then(|_| self.out.push(&[0; QOI_PADDING]))
+            .expect("Writing to a Vec cannot fail");
+        let body = self.out.into_inner().expect("Writing to a Vec cannot fail");
+
+        write_header(
+            &mut self.writer,
+            self.width,
+            self.height,
+            self.channels,
+            Colorspace::default(),
+            0,
+            size_field(body.len())?,
+        )?;
+        self.writer.write_all(&body)?;
+
+        Ok((HEADER_SIZE + body.len()) as u64)
+    }
+}
+
+/// How often one kind of opcode was used
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpcodeStats {
+    /// Number of opcodes
+    pub count: usize,
+    /// Number of pixels they encode. Only differs from `count` for runs
+    pub pixels: usize,
+    /// Bytes they occupy, including any bytes following the opcode's first
+    pub bytes: usize,
+}
+
+impl OpcodeStats {
+    fn add(&mut self, pixels: usize, bytes: usize) {
+        self.count += 1;
+        self.pixels += pixels;
+        self.bytes += bytes;
+    }
+}
+
+/// Breakdown of an encoded image by opcode, from [`encode_with_stats`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EncodeStats {
+    pub index: OpcodeStats,
+    pub run_8: OpcodeStats,
+    pub run_16: OpcodeStats,
+    pub diff_8: OpcodeStats,
+    pub diff_16: OpcodeStats,
+    pub diff_24: OpcodeStats,
+    pub color: OpcodeStats,
+    /// Size of the whole encoded image, including the header and padding
+    pub total_bytes: usize,
+}
+
+/// Encodes the image like `encode_to_vec` and writes it out, returning how it compressed. Doesn't
+/// require the writer to implement `Seek`
+pub fn encode_with_stats<W: Write>(
+    mut writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+) -> Result<EncodeStats> {
+    let (_, _, total_pixels) = verify_and_calculate_dims(data, width, channels)?;
+    let buf = encode_to_vec(data, width, channels)?;
+    writer.write_all(&buf)?;
+
+    let mut stats = tally_opcodes(&buf[HEADER_SIZE..], total_pixels);
+    stats.total_bytes = buf.len();
+
+    Ok(stats)
+}
+
+/// Counts the opcodes in valid compressed data encoding `pixels` pixels
+fn tally_opcodes(body: &[u8], pixels: usize) -> EncodeStats {
+    let mut stats = EncodeStats::default();
+
+    let (mut pos, mut decoded) = (0, 0);
+    while decoded < pixels {
+        let b1 = body[pos];
+
+        // (kind, pixels encoded, opcode length)
+        let (kind, run, len) = if (b1 & QOI_MASK_2) == QOI_INDEX {
+            (&mut stats.index, 1, 1)
+        } else if (b1 & QOI_MASK_3) == QOI_RUN_8 {
+            (&mut stats.run_8, (b1 & 0x1f) as usize + 1, 1)
+        } else if (b1 & QOI_MASK_3) == QOI_RUN_16 {
+            let run = ((((b1 & 0x1f) as usize) << 8) | body[pos + 1] as usize) + 33;
+            (&mut stats.run_16, run, 2)
+        } else if (b1 & QOI_MASK_2) == QOI_DIFF_8 {
+            (&mut stats.diff_8, 1, 1)
+        } else if (b1 & QOI_MASK_3) == QOI_DIFF_16 {
+            (&mut stats.diff_16, 1, 2)
+        } else if (b1 & QOI_MASK_4) == QOI_DIFF_24 {
+            (&mut stats.diff_24, 1, 3)
+        } else {
+            let len = 1 + (b1 & 0x0f).count_ones() as usize;
+            (&mut stats.color, 1, len)
+        };
+
+        kind.add(run, len);
+        pos += len;
+        decoded += run;
+    }
+
+    stats
+}
+
+/// An upper bound on the encoded size in bytes of any image with these dimensions, including the
+/// CRC written by `encode_with_crc`. Every pixel costs at most a `QOI_COLOR` opcode and a byte for
+/// each channel it can change; gray and RGB pixels never change alpha. Saturates at `usize::MAX`
+/// for sizes which don't fit in memory
+pub fn max_encoded_size(width: u32, height: u32, channels: ChannelCount) -> usize {
+    let max_pixel_size = match channels {
+        ChannelCount::Gray | ChannelCount::Rgb => 4,
+        ChannelCount::Rgba => 5,
+    };
+    (width as usize)
+        .saturating_mul(height as usize)
+        .saturating_mul(max_pixel_size)
+        .saturating_add(HEADER_SIZE + QOI_PADDING + CRC_SIZE)
+}
+
+/// A lower bound on the encoded size in bytes of any image with these dimensions, reached when
+/// every pixel is opaque black, the initial previous pixel, and the whole image is one run
+pub fn min_encoded_size(width: u32, height: u32) -> usize {
+    HEADER_SIZE + min_opcodes_len(width, height) + QOI_PADDING
+}
+
+/// The fewest bytes of opcodes which can encode an image with these dimensions. A run holds at
+/// most `MAX_RUN_LENGTH` pixels, so a large image takes several
+fn min_opcodes_len(width: u32, height: u32) -> usize {
+    let pixels = (width as usize).saturating_mul(height as usize);
+    let full_runs = pixels / MAX_RUN_LENGTH as usize;
+    let last_run = match pixels % MAX_RUN_LENGTH as usize {
+        0 => 0,
+        run if run < MAX_RUN_8_LENGTH as usize => 1,
+        _ => 2,
+    };
+    full_runs * 2 + last_run
+}
+
+/// Writes the opcodes for the given rows of pixels followed by the padding, and returns the number of bytes written.
+/// Each row must be tightly packed, but rows need not be contiguous with each other, and may hold
+/// several image rows of `width` pixels. Only the opcode choices in `options` are used
+fn encode_pixels<'a, W: Write>(
+    writer: W,
+    rows: impl Iterator<Item = &'a [u8]>,
+    width: u32,
+    channels: ChannelCount,
+    options: &EncodeOptions,
+) -> io::Result<usize> {
+    match channels {
+        ChannelCount::Gray => encode_pixels_n::<_, 1>(writer, rows, width, options),
+        ChannelCount::Rgb => encode_pixels_n::<_, 3>(writer, rows, width, options),
+        ChannelCount::Rgba => encode_pixels_n::<_, 4>(writer, rows, width, options),
+    }
+}
+
+/// `encode_pixels` specialized for `N` bytes per pixel
+fn encode_pixels_n<'a, W: Write, const N: usize>(
+    writer: W,
+    rows: impl Iterator<Item = &'a [u8]>,
+    width: u32,
+    options: &EncodeOptions,
+) -> io::Result<usize> {
+    let mut out = OutputBuffer::new(writer);
+    let mut state = EncoderState::new(options.use_index);
+
+    for rows in rows {
+        if options.reset_rows {
+            for row in rows.chunks(width as usize * N) {
+                state.start_row(&mut out)?;
+                encode_row::<_, N>(&mut state, row, &mut out)?;
+            }
+        } else {
+            encode_row::<_, N>(&mut state, rows, &mut out)?;
+        }
+    }
+
+    // Dump any current run
+    state.flush_run(&mut out)?;
+
+    // Padding
+    out.push(&[0; QOI_PADDING])?;
+
+    out.finish()
+}
+
+/// Encodes tightly packed pixels, continuing from `state`
+fn encode_row<W: Write, const N: usize>(
+    state: &mut EncoderState,
+    row: &[u8],
+    out: &mut OutputBuffer<W>,
+) -> io::Result<()> {
+    let mut rest = row;
+    while let Some((pixel_data, tail)) = rest.split_first_chunk::<N>() {
+        let px = expand_pixel(pixel_data);
+
+        if px == state.px_prev {
+            // Consume the whole run of matching pixels at once
+            let run = 1 + matching_pixels(tail, pixel_data);
+            state.push_run(run, out)?;
+            rest = &tail[(run - 1) * N..];
+        } else {
+            state.push(px, out)?;
+            rest = tail;
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts one pixel's worth of data to RGBA, broadcasting gray to the color channels
+fn expand_pixel<const N: usize>(pixel_data: &[u8; N]) -> Rgba {
+    match N {
+        1 => [pixel_data[0], pixel_data[0], pixel_data[0], 0xFF],
+        3 => [pixel_data[0], pixel_data[1], pixel_data[2], 0xFF],
+        _ => [pixel_data[0], pixel_data[1], pixel_data[2], pixel_data[3]],
+    }
+}
+
+/// Counts the pixels at the start of `data` which are identical to `pixel_data`
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn matching_pixels(data: &[u8], pixel_data: &[u8]) -> usize {
+    data.chunks_exact(pixel_data.len())
+        .take_while(|&p| p == pixel_data)
+        .count()
+}
+
+/// Counts the pixels at the start of `data` which are identical to `pixel_data`, comparing 48
+/// bytes (a whole number of 1, 3 or 4 byte pixels) per step using SSE2
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn matching_pixels(data: &[u8], pixel_data: &[u8]) -> usize {
+    use std::arch::x86_64::{
+        __m128i, _mm_and_si128, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8,
+    };
+
+    const BLOCK: usize = 48;
+    let bytes_per_pixel = pixel_data.len();
+
+    let mut pattern = [0u8; BLOCK];
+    for chunk in pattern.chunks_exact_mut(bytes_per_pixel) {
+        chunk.copy_from_slice(pixel_data);
+    }
+
+    let mut matched = 0;
+    // SAFETY: SSE2 is part of the x86_64 baseline, and every load reads 16 bytes from within a
+    // 48 byte block of `pattern` or `data`
+    unsafe {
+        let load =
+            |block: &[u8], i: usize| _mm_loadu_si128(block[i * 16..].as_ptr() as *const __m128i);
+        let pat = [load(&pattern, 0), load(&pattern, 1), load(&pattern, 2)];
+
+        for block in data.chunks_exact(BLOCK) {
+            let eq = _mm_and_si128(
+                _mm_and_si128(
+                    _mm_cmpeq_epi8(load(block, 0), pat[0]),
+                    _mm_cmpeq_epi8(load(block, 1), pat[1]),
+                ),
+                _mm_cmpeq_epi8(load(block, 2), pat[2]),
+            );
+            if _mm_movemask_epi8(eq) != 0xFFFF {
+                break;
+            }
+            matched += BLOCK;
+        }
+    }
+
+    // Finish off the partially matching block one pixel at a time
+    matched / bytes_per_pixel
+        + data[matched..]
+            .chunks_exact(bytes_per_pixel)
+            .take_while(|&p| p == pixel_data)
+            .count()
+}
+
+/// The encoder's state carried from one pixel to the next
+struct EncoderState {
+    run: u32,          // Run length encoding run length
+    px_prev: Rgba,     // Previous pixel
+    index: ColorIndex, // Recently seen pixels
+    use_index: bool,   // Whether to emit QOI_INDEX at all
+}
+
+impl EncoderState {
+    fn new(use_index: bool) -> Self {
+        Self {
+            run: 0,
+            px_prev: DEFAULT_PREV_PIXEL,
+            index: ColorIndex::new(),
+            use_index,
+        }
+    }
+
+    /// Ends any run and resets the previous pixel, for `EncodeOptions::reset_rows`
+    fn start_row<W: Write>(&mut self, out: &mut OutputBuffer<W>) -> io::Result<()> {
+        self.flush_run(out)?;
+        self.px_prev = DEFAULT_PREV_PIXEL;
+        Ok(())
+    }
+
+    /// Extends the current run by `count` more copies of the previous pixel
+    fn push_run<W: Write>(
+        &mut self,
+        mut count: usize,
+        out: &mut OutputBuffer<W>,
+    ) -> io::Result<()> {
+        while count > 0 {
+            let step = count.min((MAX_RUN_LENGTH - self.run) as usize);
+            self.run += step as u32;
+            count -= step;
+
+            // We've reached the max run length
+            if self.run == MAX_RUN_LENGTH {
+                self.flush_run(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out the current run, if there is one
+    fn flush_run<W: Write>(&mut self, out: &mut OutputBuffer<W>) -> io::Result<()> {
+        if self.run == 0 {
+            return Ok(());
+        }
+
+        if self.run < MAX_RUN_8_LENGTH {
+            // Write a short run length
+            let run = self.run - 1;
+            out.push(&[QOI_RUN_8 | run as u8])?;
+        } else {
+            // Write a long run length
+            let run = self.run - MAX_RUN_8_LENGTH;
+            out.push(&[QOI_RUN_16 | (run >> 8) as u8, run as u8])?;
+        }
+        self.run = 0;
+
+        Ok(())
+    }
+
+    /// Encodes the next pixel
+    fn push<W: Write>(&mut self, px: Rgba, out: &mut OutputBuffer<W>) -> io::Result<()> {
+        // Pixel matches the previous one, increase run length
+        if px == self.px_prev {
+            return self.push_run(1, out);
+        }
+
+        // The pixel doesn't match, so we must dump any current run
+        self.flush_run(out)?;
+
+        let index_pos = ColorIndex::slot(px);
+
+        if self.use_index && px == self.index.get(index_pos) {
+            out.push(&[QOI_INDEX | index_pos])?;
+        } else {
+            if self.use_index {
+                self.index.insert(px);
+            }
+            let diff = subtract_pixels(px, self.px_prev);
+            let [vr, vg, vb, va] = diff;
+
+            let within_small_diff = diff.into_iter().all(|v| v > -16 && v < 17);
+
+            if within_small_diff {
+                // Use difference encoding
+                if va == 0 && vr > -2 && vr < 3 && vg > -2 && vg < 3 && vb > -2 && vb < 3 {
+                    // Use 2-bit difference encoding
+                    out.push(&[QOI_DIFF_8 | (((vr + 1) << 4) | (vg + 1) << 2 | (vb + 1)) as u8])?;
+                } else if va == 0 && vr > -16 && vr < 17 && vg > -8 && vg < 9 && vb > -8 && vb < 9 {
+                    // Use 5 or 4-bit difference encoding
+                    out.push(&[
+                        QOI_DIFF_16 | (vr + 15) as u8,
+                        (((vg + 7) << 4) | (vb + 7)) as u8,
+                    ])?;
+                } else {
+                    // Use 5-bit difference encoding
+                    out.push(&[
+                        QOI_DIFF_24 | ((vr + 15) >> 1) as u8,
+                        (((vr + 15) << 7) | ((vg + 15) << 2) | ((vb + 15) >> 3)) as u8,
+                        (((vb + 15) << 5) | (va + 15)) as u8,
+                    ])?;
+                }
+            } else {
+                // Encode an entire pixel (but only the differing components)
+                let gate = |v: i32, x: u8| if v != 0 { x } else { 0 };
+
+                out.push(&[QOI_COLOR | gate(vr, 8) | gate(vg, 4) | gate(vb, 2) | gate(va, 1)])?;
+
+                if vr != 0 {
+                    out.push(&[px[0]])?;
+                }
+                if vg != 0 {
+                    out.push(&[px[1]])?;
+                }
+                if vb != 0 {
+                    out.push(&[px[2]])?;
+                }
+                if va != 0 {
+                    out.push(&[px[3]])?;
+                }
+            }
+        }
+
+        self.px_prev = px;
+
+        Ok(())
+    }
+}
+
+/// Size of the batches in which encoded opcodes are handed to the underlying writer
+const OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Batches the encoder's small opcode writes into larger writes to the underlying writer
+struct OutputBuffer<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+    /// Total bytes pushed so far
+    len: usize,
+}
+
+impl<W: Write> OutputBuffer<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::with_capacity(OUTPUT_BUFFER_SIZE),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.buf.len() + bytes.len() > OUTPUT_BUFFER_SIZE {
+            self.flush()?;
+        }
+        self.buf.extend_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Flushes any remaining bytes and returns the total number of bytes written
+    fn finish(mut self) -> io::Result<usize> {
+        self.flush()?;
+        Ok(self.len)
+    }
+
+    /// Flushes any remaining bytes and returns the underlying writer
+    fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Passes writes through while keeping a running CRC32 of everything written
+struct CrcWriter<W: Write> {
+    writer: W,
+    crc: u32,
+}
+
+impl<W: Write> CrcWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer, crc: 0 }
+    }
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(bytes)?;
+        self.crc = crc32(self.crc, &bytes[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes into a fixed buffer, failing without writing anything once a write doesn't fit
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    /// Length the buffer would have needed for the writes attempted so far
+    required: usize,
+}
+
+impl<'a> SliceWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            required: 0,
+        }
+    }
+}
+
+impl Write for SliceWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.required = self.len + bytes.len();
+        let dest = self
+            .buf
+            .get_mut(self.len..self.required)
+            .ok_or(io::ErrorKind::WriteZero)?;
+        dest.copy_from_slice(bytes);
+        self.len = self.required;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Lookup table for the reflected CRC32 polynomial used by zlib and PNG
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the CRC32 `crc` (0 to start) over `data`
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Returns (width, height, total_pixels) and verifies that the image dimensions and channel count match the data
+pub fn verify_and_calculate_dims(
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+) -> Result<(u32, u32, usize)> {
+    calculate_dims(data.len(), width, channels)
+}
+
+/// Like `verify_and_calculate_dims`, for `len` samples of any size
+fn calculate_dims(len: usize, width: usize, channels: ChannelCount) -> Result<(u32, u32, usize)> {
+    // Before anything divides by the width
+    if width == 0 {
+        return Err(QoiError::ZeroDimension);
+    }
+
+    // QOI has no representation for a 0x0 image
+    if len == 0 {
+        return Err(QoiError::EmptyImage);
+    }
+
+    // Check that the width and data length match up
+    if !len.is_multiple_of(channels as usize) {
+        return Err(QoiError::DataNotMultipleOfChannels);
+    }
+    // The width is in pixels, so compare whole rows rather than bytes
+    let row_len = width
+        .checked_mul(channels as usize)
+        .ok_or(QoiError::DimensionTooLarge)?;
+    if !len.is_multiple_of(row_len) {
+        return Err(QoiError::WidthDoesNotDivideData);
+    }
+    let height = len / row_len;
+
+    let height = dimension_field(height)?;
+    let width = dimension_field(width)?;
+    let total_pixels = len / channels as usize;
+
+    Ok((width, height, total_pixels))
+}
+
+/// Converts a width or height to its header field, failing with `DimensionTooLarge` past
+/// `MAX_DIMENSION`. Every encoder checks its dimensions here
+pub(crate) fn dimension_field(dimension: usize) -> Result<u32> {
+    u32::try_from(dimension)
+        .ok()
+        .filter(|&dimension| dimension <= MAX_DIMENSION)
+        .ok_or(QoiError::DimensionTooLarge)
+}
+
+/// Converts the length of the compressed data to its header field, failing with
+/// `CompressedTooLarge` rather than truncating it. Every encoder checks its length here
+pub(crate) fn size_field(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| QoiError::CompressedTooLarge { len })
+}
+
+/// Checks the width and height read from a header. Every decoder checks its dimensions here
+pub(crate) fn check_dimensions(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(QoiError::ZeroDimension);
+    }
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(QoiError::DimensionTooLarge);
+    }
+    Ok(())
+}
+
+/// Returns the offset at which the file size will be written
+fn encode_header<W: Write + Seek>(
+    mut writer: W,
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    colorspace: Colorspace,
+    flags: u8,
+) -> io::Result<u64> {
+    let start = writer.stream_position()?;
+    write_header(&mut writer, width, height, channels, colorspace, flags, 0)?;
+    Ok(start + SIZE_FIELD_OFFSET as u64)
+}
+
+fn write_header<W: Write>(
+    mut writer: W,
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    colorspace: Colorspace,
+    flags: u8,
+    size: u32,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION])?;
+    writer.write_all(&width.to_le_bytes())?;
+    writer.write_all(&height.to_le_bytes())?;
+    writer.write_all(&[channels as u8])?;
+    writer.write_all(&[colorspace as u8])?;
+    writer.write_all(&[flags])?;
+    writer.write_all(&size.to_le_bytes())
+}
+
+/// Writes the compressed size into the header field at `offset`, then returns to the end of the
+/// stream so that the caller can carry on writing after it
+fn encode_size<W: Write + Seek>(mut writer: W, size: u32, offset: u64) -> io::Result<()> {
+    let end = writer.stream_position()?;
+    writer.seek(SeekFrom::Start(offset))?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+/// The fields of a stream's header. Multi-byte fields are little-endian whatever the host, so
+/// files move between machines unchanged:
+///
+/// | Field           | Size   |
+/// |-----------------|--------|
+/// | Magic `qoiv`    | 4      |
+/// | Version         | 1      |
+/// | Width           | 4 (LE) |
+/// | Height          | 4 (LE) |
+/// | Channels        | 1      |
+/// | Colorspace      | 1      |
+/// | Flags           | 1      |
+/// | Compressed size | 4 (LE) |
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QoiHeader {
+    pub width: u32,
+    pub height: u32,
+    /// Channel count the image was encoded from
+    pub channels: ChannelCount,
+    pub colorspace: Colorspace,
+    /// Whether a CRC32 follows the compressed data
+    pub has_crc: bool,
+    /// Whether the previous pixel is reset at the start of every row, see
+    /// [`EncodeOptions::reset_rows`]
+    pub resets_rows: bool,
+    /// Length of the compressed data following the header, including the padding but not the CRC
+    pub compressed_len: u32,
+}
+
+impl QoiHeader {
+    /// Length in bytes of the whole stream: the header, the compressed data and any CRC
+    pub fn stream_len(&self) -> u64 {
+        HEADER_SIZE as u64 + self.body_len()
+    }
+
+    /// Length of everything following the header
+    fn body_len(&self) -> u64 {
+        let trailer_len = if self.has_crc { CRC_SIZE as u64 } else { 0 };
+        self.compressed_len as u64 + trailer_len
+    }
+
+    /// Number of pixels in the image. Saturates where usize is 32 bits, like `uncompressed_len`
+    fn pixel_count(&self) -> usize {
+        (self.width as usize).saturating_mul(self.height as usize)
+    }
+}
+
+/// Reads and validates the header. Reads exactly the header bytes and no more
+pub fn read_header<R: Read>(mut reader: R) -> Result<QoiHeader> {
+    let mut byte_buf = [0u8; 1];
+    let mut long_buf = [0u8; 4];
+
+    // Check magic
+    reader.read_exact(&mut long_buf)?;
+    if &long_buf == LEGACY_MAGIC {
+        return Err(QoiError::UnsupportedVersion(0));
+    }
+    if &long_buf != MAGIC {
+        return Err(QoiError::BadMagic(long_buf));
+    }
+
+    // Check version
+    reader.read_exact(&mut byte_buf)?;
+    if byte_buf[0] != FORMAT_VERSION {
+        return Err(QoiError::UnsupportedVersion(byte_buf[0]));
+    }
+
+    // Read width, height
+    reader.read_exact(&mut long_buf)?;
+    let width = u32::from_le_bytes(long_buf);
+
+    reader.read_exact(&mut long_buf)?;
+    let height = u32::from_le_bytes(long_buf);
+
+    check_dimensions(width, height)?;
+
+    // Read channel count
+    reader.read_exact(&mut byte_buf)?;
+    let channels = ChannelCount::try_from(byte_buf[0])?;
+
+    // Read colorspace
+    reader.read_exact(&mut byte_buf)?;
+    let colorspace = match byte_buf[0] {
+        0 => Colorspace::Srgb,
+        1 => Colorspace::Linear,
+        other => return Err(QoiError::BadColorspace(other)),
+    };
+
+    // Read flags
+    reader.read_exact(&mut byte_buf)?;
+    if byte_buf[0] & !(FLAG_CRC | FLAG_RESET_ROWS) != 0 {
+        return Err(QoiError::UnsupportedFlags(byte_buf[0]));
+    }
+    let has_crc = byte_buf[0] & FLAG_CRC != 0;
+    let resets_rows = byte_buf[0] & FLAG_RESET_ROWS != 0;
+
+    // Read compressed size
+    reader.read_exact(&mut long_buf)?;
+
+    let compressed_data_len = u32::from_le_bytes(long_buf);
+
+    // Catch a zeroed or truncated size here rather than partway through decoding
+    let min_len = min_opcodes_len(width, height) + QOI_PADDING;
+    if (compressed_data_len as usize) < min_len {
+        return Err(QoiError::SizeMismatch {
+            declared: compressed_data_len,
+            actual: min_len,
+        });
+    }
+
+    Ok(QoiHeader {
+        width,
+        height,
+        channels,
+        colorspace,
+        has_crc,
+        resets_rows,
+        compressed_len: compressed_data_len,
+    })
+}
+
+/// Returns (width, height) without decoding any pixels. Reads exactly the header bytes and no more
+pub fn dimensions<R: Read>(reader: R) -> Result<(u32, u32)> {
+    let header = read_header(reader)?;
+    Ok((header.width, header.height))
+}
+
+/// Returns (image data, width, height). Refuses images larger than `DEFAULT_DECODE_LIMIT` bytes
+pub fn decode<R: Read>(reader: R, channels: ChannelCount) -> Result<(Vec<u8>, u32, u32)> {
+    decode_with_limit(reader, channels, DEFAULT_DECODE_LIMIT)
+}
+
+/// Returns (image data, width, height), failing before any allocation if the decoded image would exceed `max_bytes`
+pub fn decode_with_limit<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+    max_bytes: usize,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(uncompressed_len(width, height, channels), max_bytes)?;
+    decode_body(reader, &header, channels, PixelOrder::Rgba, &mut out_buf)?;
+
+    Ok((out_buf, width, height))
+}
+
+/// Like `decode`, but returns the image data as a boxed slice, for storing in long-lived structs
+/// where it should never grow. The decoded `Vec` is already exactly full, so this doesn't copy.
+/// Returns (image data, width, height)
+pub fn decode_boxed<R: Read>(reader: R, channels: ChannelCount) -> Result<(Box<[u8]>, u32, u32)> {
+    let (data, width, height) = decode(reader, channels)?;
+    Ok((data.into_boxed_slice(), width, height))
+}
+
+/// Like `decode`, but writes the color channels of each pixel in `order`. Returns (image data,
+/// width, height)
+pub fn decode_with_order<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+    order: PixelOrder,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    decode_body(reader, &header, channels, order, &mut out_buf)?;
+
+    Ok((out_buf, width, height))
+}
+
+/// Returns (image data, width, height, colorspace)
+pub fn decode_with_colorspace<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+) -> Result<(Vec<u8>, u32, u32, Colorspace)> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    decode_body(reader, &header, channels, PixelOrder::Rgba, &mut out_buf)?;
+
+    Ok((out_buf, width, height, header.colorspace))
+}
+
+/// Like `decode`, also returning the number of bytes the stream occupied, including the header,
+/// padding and any CRC. The reader is left just past the stream, so whatever follows it can be
+/// read next. Returns (image data, width, height, bytes consumed)
+pub fn decode_and_consumed<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+) -> Result<(Vec<u8>, u32, u32, u64)> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    decode_body(reader, &header, channels, PixelOrder::Rgba, &mut out_buf)?;
+
+    Ok((out_buf, width, height, header.stream_len()))
+}
+
+/// Checks that a stream decodes successfully, without allocating the decoded image. Every check
+/// `decode` makes is made here, including `DEFAULT_DECODE_LIMIT`, so with the stored channel count
+/// this fails exactly when `decode` would. Returns (width, height)
+pub fn verify<R: Read>(mut reader: R) -> Result<(u32, u32)> {
+    let header = read_header(&mut reader)?;
+    let required = uncompressed_len(header.width, header.height, header.channels);
+    if required > DEFAULT_DECODE_LIMIT {
+        return Err(QoiError::TooLarge {
+            required,
+            limit: DEFAULT_DECODE_LIMIT,
+        });
+    }
+    let body = read_body(reader, header.body_len())?;
+    check_crc(&body, &header)?;
+
+    let opcodes = &body[..body.len().min(header.compressed_len as usize)];
+    let mut bytes = opcodes.iter().copied();
+    let mut state = OpcodeState::new();
+    state.configure(&header);
+    for _ in 0..header.pixel_count() {
+        state.next_pixel(|| bytes.next().ok_or(QoiError::UnexpectedEof))?;
+    }
+
+    let opcodes_len = opcodes.len() - bytes.len();
+    check_end(opcodes, opcodes_len, header.compressed_len, &state)?;
+
+    Ok((header.width, header.height))
+}
+
+/// Decodes only the `w` by `h` pixel rectangle whose top left corner is at (`x`, `y`), e.g. the
+/// visible part of a large map. Every pixel must still be decoded, since each opcode depends on the
+/// ones before it, but only the region is kept in memory. Returns the region's image data
+pub fn decode_region<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+) -> Result<Vec<u8>> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    if w == 0 || h == 0 {
+        return Err(QoiError::ZeroDimension);
+    }
+    let in_bounds =
+        |start: u32, len: u32, size: u32| start.checked_add(len).is_some_and(|end| end <= size);
+    if !in_bounds(x, w, width) || !in_bounds(y, h, height) {
+        return Err(QoiError::RegionOutOfBounds);
+    }
+
+    let mut out_buf = alloc_output(uncompressed_len(w, h, channels), DEFAULT_DECODE_LIMIT)?;
+
+    let body = read_body(reader, header.body_len())?;
+    check_crc(&body, &header)?;
+
+    let opcodes = &body[..body.len().min(header.compressed_len as usize)];
+    let mut bytes = opcodes.iter().copied();
+    let mut read_byte = || bytes.next().ok_or(QoiError::UnexpectedEof);
+    let mut state = OpcodeState::new();
+    state.configure(&header);
+
+    let bytes_per_pixel = channels.bytes_per_pixel();
+    let mut out_rows = out_buf.chunks_exact_mut(w as usize * bytes_per_pixel);
+    for row in 0..height {
+        let mut out_row = if (y..y + h).contains(&row) {
+            out_rows.next()
+        } else {
+            None
+        };
+        for col in 0..width {
+            let px = state.next_pixel(&mut read_byte)?;
+            if let Some(out_row) = out_row.as_deref_mut() {
+                if (x..x + w).contains(&col) {
+                    let start = (col - x) as usize * bytes_per_pixel;
+                    out_row[start..start + bytes_per_pixel].copy_from_slice(&px[..bytes_per_pixel]);
+                }
+            }
+        }
+    }
+
+    let opcodes_len = opcodes.len() - bytes.len();
+    check_end(opcodes, opcodes_len, header.compressed_len, &state)?;
+
+    Ok(out_buf)
+}
+
+/// The result of [`decode_planar`]
+#[derive(Debug)]
+pub struct PlanarImage {
+    pub red: Vec<u8>,
+    pub green: Vec<u8>,
+    pub blue: Vec<u8>,
+    /// Only present for RGBA images
+    pub alpha: Option<Vec<u8>>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Decodes into one plane per channel, e.g. for the layers of a GPU array texture. Each pixel is
+/// scattered into the planes as it is decoded, so there is no separate de-interleaving pass. Gray
+/// images give three identical planes. Refuses images whose planes together are larger than
+/// `DEFAULT_DECODE_LIMIT` bytes
+pub fn decode_planar<R: Read>(mut reader: R, channels: ChannelCount) -> Result<PlanarImage> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let plane_channels = match channels {
+        ChannelCount::Rgba => ChannelCount::Rgba,
+        _ => ChannelCount::Rgb,
+    };
+    let required = uncompressed_len(width, height, plane_channels);
+    if required > DEFAULT_DECODE_LIMIT {
+        return Err(QoiError::TooLarge {
+            required,
+            limit: DEFAULT_DECODE_LIMIT,
+        });
+    }
+
+    let total_pixels = header.pixel_count();
+    let mut red = vec![0; total_pixels];
+    let mut green = vec![0; total_pixels];
+    let mut blue = vec![0; total_pixels];
+    let mut alpha = (channels == ChannelCount::Rgba).then(|| vec![0; total_pixels]);
+
+    let body = read_body(reader, header.body_len())?;
+    check_crc(&body, &header)?;
+
+    let opcodes = &body[..body.len().min(header.compressed_len as usize)];
+    let mut bytes = opcodes.iter().copied();
+    let mut read_byte = || bytes.next().ok_or(QoiError::UnexpectedEof);
+    let mut state = OpcodeState::new();
+    state.configure(&header);
+
+    for i in 0..total_pixels {
+        let [r, g, b, a] = state.next_pixel(&mut read_byte)?;
+        red[i] = r;
+        green[i] = g;
+        blue[i] = b;
+        if let Some(alpha) = alpha.as_deref_mut() {
+            alpha[i] = a;
+        }
+    }
+
+    let opcodes_len = opcodes.len() - bytes.len();
+    check_end(opcodes, opcodes_len, header.compressed_len, &state)?;
+
+    Ok(PlanarImage {
+        red,
+        green,
+        blue,
+        alpha,
+        width,
+        height,
+    })
+}
+
+/// Decodes directly from an in-memory buffer, without copying the compressed data. Returns (image data, width, height)
+pub fn decode_slice(data: &[u8], channels: ChannelCount) -> Result<(Vec<u8>, u32, u32)> {
+    let mut body = data;
+    let header = read_header(&mut body)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    decode_body_slice(
+        body,
+        &header,
+        channels,
+        PixelOrder::Rgba,
+        &mut out_buf,
+        &mut OpcodeState::new(),
+    )?;
+
+    Ok((out_buf, width, height))
+}
+
+/// Decodes to `output` channels whatever the channel count stored in the file. Alpha is dropped
+/// when decoding to RGB or gray, and is 255 when an RGB or gray image is decoded to RGBA. Color
+/// images decoded to gray keep only the red channel. Returns (image data, width, height)
+pub fn decode_converted<R: Read>(reader: R, output: ChannelCount) -> Result<(Vec<u8>, u32, u32)> {
+    decode_with_options(reader, output, &DecodeOptions::new())
+}
+
+/// Settings for [`decode_with_options`]. The defaults match `decode_converted`
+#[derive(Copy, Clone, Debug)]
+pub struct DecodeOptions {
+    rgb_to_rgba_alpha: u8,
+    flip_vertical: bool,
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Self {
+            rgb_to_rgba_alpha: 0xFF,
+            flip_vertical: false,
+        }
+    }
+
+    /// The alpha given to every pixel when an RGB or gray image is decoded to RGBA, e.g. 0 to
+    /// composite it as fully transparent. 255 by default
+    pub fn rgb_to_rgba_alpha(mut self, alpha: u8) -> Self {
+        self.rgb_to_rgba_alpha = alpha;
+        self
+    }
+
+    /// Whether to output the bottom row first, e.g. for uploading to an OpenGL texture
+    pub fn flip_vertical(mut self, flip_vertical: bool) -> Self {
+        self.flip_vertical = flip_vertical;
+        self
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Like `decode_converted`, with the given options. Returns (image data, width, height)
+pub fn decode_with_options<R: Read>(
+    mut reader: R,
+    output: ChannelCount,
+    options: &DecodeOptions,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let header = read_header(&mut reader)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, output),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    decode_body(reader, &header, output, PixelOrder::Rgba, &mut out_buf)?;
+
+    if output == ChannelCount::Rgba && header.channels != ChannelCount::Rgba {
+        fill_alpha(&mut out_buf, options.rgb_to_rgba_alpha);
+    }
+    if options.flip_vertical {
+        flip_rows(&mut out_buf, width as usize * output.bytes_per_pixel());
+    }
+
+    Ok((out_buf, width, height))
+}
+
+/// Reverses the order of the rows of `row_len` bytes in `data`, in place
+fn flip_rows(data: &mut [u8], row_len: usize) {
+    let height = data.len() / row_len;
+    for y in 0..height / 2 {
+        let (top, bottom) = data.split_at_mut((height - 1 - y) * row_len);
+        top[y * row_len..][..row_len].swap_with_slice(&mut bottom[..row_len]);
+    }
+}
+
+/// Sets the alpha channel of every pixel in RGBA data
+fn fill_alpha(rgba: &mut [u8], alpha: u8) {
+    for px in rgba.chunks_exact_mut(4) {
+        px[3] = alpha;
+    }
+}
+
+/// Like `decode`, but only accepts the exact stream this crate's encoder produces for the image.
+/// Fails with `UnwrittenIndex` if the stream refers to a slot of the color index which no earlier
+/// pixel has filled, and with `NonCanonical` if it encodes any pixel with a different opcode than
+/// the encoder would have, e.g. a diff where an index hit was possible. This re-encodes the image,
+/// so it takes about twice as long as `decode`. Returns (image data, width, height)
+pub fn decode_strict<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut rgba = alloc_output(
+        uncompressed_len(width, height, ChannelCount::Rgba),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    let body = read_body(reader, header.body_len())?;
+    decode_body_slice(
+        &body,
+        &header,
+        ChannelCount::Rgba,
+        PixelOrder::Rgba,
+        &mut rgba,
+        &mut OpcodeState::strict(),
+    )?;
+
+    // Encode the image again from data with the channel count in the header, so that e.g. alpha
+    // in an RGB stream also shows up as a difference
+    let stored_data = match header.channels {
+        ChannelCount::Rgba => None,
+        stored_channels => Some(drop_channels(&rgba, stored_channels)),
+    };
+    let mut canonical = Vec::with_capacity(header.compressed_len as usize);
+    encode_pixels(
+        &mut canonical,
+        std::iter::once(stored_data.as_deref().unwrap_or(&rgba)),
+        width,
+        header.channels,
+        &EncodeOptions::new().reset_rows(header.resets_rows),
+    )
+    .expect("Writing to a Vec cannot fail");
+
+    let stored = &body[..header.compressed_len as usize];
+    if canonical != stored {
+        let offset = canonical
+            .iter()
+            .zip(stored)
+            .position(|(a, b)| a != b)
+            .unwrap_or(canonical.len().min(stored.len()));
+        return Err(QoiError::NonCanonical {
+            offset: HEADER_SIZE + offset,
+        });
+    }
+
+    let out_buf = match channels {
+        ChannelCount::Rgba => rgba,
+        _ => drop_channels(&rgba, channels),
+    };
+
+    Ok((out_buf, width, height))
+}
+
+/// The result of [`decode_partial`]
+#[derive(Debug)]
+pub struct PartialImage {
+    /// Image data for the whole image. Pixels from `pixels_decoded` onwards are zero
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Number of pixels, in row order, which were decoded before the error
+    pub pixels_decoded: usize,
+    /// Why decoding stopped early, or `None` if the whole image decoded successfully
+    pub error: Option<QoiError>,
+}
+
+/// Decodes as much of the image as possible, e.g. so that the top of a truncated download can be
+/// shown. Fails only if the header is invalid, the stream holds another channel count than
+/// `channels`, or the image exceeds `DEFAULT_DECODE_LIMIT` bytes; errors in the compressed data
+/// are reported in [`PartialImage::error`]
+pub fn decode_partial<R: Read>(mut reader: R, channels: ChannelCount) -> Result<PartialImage> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut data = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+
+    // Keep whatever arrived before a read error
+    let mut body = Vec::new();
+    let read_result = (&mut reader).take(header.body_len()).read_to_end(&mut body);
+
+    let opcodes = &body[..body.len().min(header.compressed_len as usize)];
+    let mut bytes = opcodes.iter().copied();
+    let mut state = OpcodeState::new();
+    state.configure(&header);
+    let mut pixels_decoded = 0;
+    let mut error = None;
+
+    for out_px in data.chunks_exact_mut(channels.bytes_per_pixel()) {
+        match state.next_pixel(|| bytes.next().ok_or(QoiError::UnexpectedEof)) {
+            Ok(px) => out_px.copy_from_slice(&px[..out_px.len()]),
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+        pixels_decoded += 1;
+    }
+
+    let error = match (read_result, error) {
+        // A failed read is the real reason the data ran out
+        (Err(err), _) => Some(err.into()),
+        (Ok(_), Some(err)) => Some(err),
+        (Ok(_), None) => {
+            let opcodes_len = opcodes.len() - bytes.len();
+            check_crc(&body, &header)
+                .and_then(|_| check_end(opcodes, opcodes_len, header.compressed_len, &state))
+                .err()
+        }
+    };
+
+    Ok(PartialImage {
+        data,
+        width,
+        height,
+        pixels_decoded,
+        error,
+    })
+}
+
+/// Keeps the first `channels` bytes of each pixel of RGBA data
+fn drop_channels(rgba: &[u8], channels: ChannelCount) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .flat_map(|px| &px[..channels.bytes_per_pixel()])
+        .copied()
+        .collect()
+}
+
+/// Decodes using the channel count stored in the file. Returns (image data, width, height, channels)
+pub fn decode_auto<R: Read>(mut reader: R) -> Result<(Vec<u8>, u32, u32, ChannelCount)> {
+    let header = read_header(&mut reader)?;
+    let (width, height, channels) = (header.width, header.height, header.channels);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    decode_body(reader, &header, channels, PixelOrder::Rgba, &mut out_buf)?;
+
+    Ok((out_buf, width, height, channels))
+}
+
+/// A decoded image together with its dimensions and channel count, for when tuples of
+/// (data, width, height) get unwieldy. `QoiImage::try_from(bytes)` decodes a stream with the
+/// channel count stored in it, and [`QoiImage::encode`] encodes it again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QoiImage {
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    pixels: Vec<u8>,
+}
+
+impl QoiImage {
+    /// Takes tightly packed image data, calculating the height as `encode` does. Fails if the
+    /// data doesn't fill whole rows or either dimension is zero or larger than `MAX_DIMENSION`
+    pub fn new(pixels: Vec<u8>, width: usize, channels: ChannelCount) -> Result<Self> {
+        let (width, height, _) = verify_and_calculate_dims(&pixels, width, channels)?;
+        Ok(Self {
+            width,
+            height,
+            channels,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// The image data, `channels` bytes per pixel in row order
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    /// The pixel at (x, y), expanded to RGBA. Panics if it is outside the image
+    pub fn get_pixel(&self, x: u32, y: u32) -> Rgba {
+        assert!(x < self.width, "x {} is outside the image", x);
+        let offset = (y as usize * self.width as usize + x as usize) * self.channels as usize;
+        match self.channels {
+            ChannelCount::Gray => expand_pixel::<1>(self.pixels[offset..][..1].try_into().unwrap()),
+            ChannelCount::Rgb => expand_pixel::<3>(self.pixels[offset..][..3].try_into().unwrap()),
+            ChannelCount::Rgba => expand_pixel::<4>(self.pixels[offset..][..4].try_into().unwrap()),
+        }
+    }
+
+    /// Row `y` of the image data. Panics if it is outside the image
+    pub fn row(&self, y: u32) -> &[u8] {
+        let row_len = self.width as usize * self.channels as usize;
+        &self.pixels[y as usize * row_len..][..row_len]
+    }
+
+    /// Encodes the image into a new buffer
+    pub fn encode(&self) -> Vec<u8> {
+        encode_to_vec(&self.pixels, self.width as usize, self.channels)
+            .expect("Dimensions were checked on construction")
+    }
+}
+
+impl TryFrom<&[u8]> for QoiImage {
+    type Error = QoiError;
+
+    /// Decodes a stream with the channel count stored in it
+    fn try_from(data: &[u8]) -> Result<Self> {
+        let (pixels, width, height, channels) = decode_auto(data)?;
+        Ok(Self {
+            width,
+            height,
+            channels,
+            pixels,
+        })
+    }
+}
+
+/// Decodes into the beginning of `out`, which must be at least `width * height * channels` bytes long. Returns (width, height)
+pub fn decode_to_slice<R: Read>(
+    mut reader: R,
+    channels: ChannelCount,
+    out: &mut [u8],
+) -> Result<(u32, u32)> {
+    let header = read_header(&mut reader)?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let required = uncompressed_len(width, height, channels);
+    if out.len() < required {
+        return Err(QoiError::BufferTooSmall {
+            required,
+            available: out.len(),
+        });
+    }
+
+    decode_body(
+        reader,
+        &header,
+        channels,
+        PixelOrder::Rgba,
+        &mut out[..required],
+    )?;
+
+    Ok((width, height))
+}
+
+/// Returns an iterator which decodes one pixel at a time as RGBA, without buffering the whole image
+pub fn decode_pixels<R: Read>(mut reader: R) -> Result<PixelIter<R>> {
+    let header = read_header(&mut reader)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut state = OpcodeState::new();
+    state.configure(&header);
+
+    Ok(PixelIter {
+        reader,
+        state,
+        width,
+        height,
+        remaining: header.pixel_count(),
+    })
+}
+
+/// Lazily decodes pixels from a reader. Opcodes are read a byte at a time, so the reader
+/// should be buffered. Stops after the first error
+pub struct PixelIter<R: Read> {
+    reader: R,
+    state: OpcodeState,
+    width: u32,
+    height: u32,
+    remaining: usize,
+}
+
+impl<R: Read> PixelIter<R> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl<R: Read> Iterator for PixelIter<R> {
+    type Item = Result<Rgba>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let reader = &mut self.reader;
+        let result = self.state.next_pixel(|| {
+            let mut buf = [0u8];
+            reader.read_exact(&mut buf)?;
+            Ok(buf[0])
+        });
+
+        self.remaining = match result {
+            Ok(_) => self.remaining - 1,
+            Err(_) => 0,
+        };
+
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+/// Push-style decoder for streams which arrive in chunks, e.g. from a socket. Bytes are handed
+/// over with [`Decoder::feed`], and [`Decoder::pixels`] yields as many RGBA pixels as the
+/// buffered bytes allow. A partial opcode at the end of the buffer is kept for the next `feed`.
+/// An invalid opcode stops decoding for good, and is reported by [`Decoder::error`]
+pub struct Decoder {
+    buf: Vec<u8>,
+    pos: usize,
+    header: Option<QoiHeader>,
+    state: OpcodeState,
+    remaining: usize,
+    error: Option<QoiError>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            pos: 0,
+            header: None,
+            state: OpcodeState::new(),
+            remaining: 0,
+            error: None,
+        }
+    }
+
+    /// Buffers more of the stream. Fails if the header is complete but invalid
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<()> {
+        // Drop bytes which have already been decoded
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(bytes);
+
+        if self.header.is_none() && self.buf.len() >= HEADER_SIZE {
+            let header = read_header(&self.buf[..HEADER_SIZE])?;
+            self.header = Some(header);
+            self.state.configure(&header);
+            self.remaining = header.pixel_count();
+            self.pos = HEADER_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the header once it has been fed
+    pub fn header(&self) -> Option<QoiHeader> {
+        self.header
+    }
+
+    /// Whether every pixel in the image has been decoded
+    pub fn is_finished(&self) -> bool {
+        self.header.is_some() && self.remaining == 0
+    }
+
+    /// The error which stopped decoding, if the stream turned out to be invalid. Until then,
+    /// running out of pixels only means more bytes are needed
+    pub fn error(&self) -> Option<&QoiError> {
+        self.error.as_ref()
+    }
+
+    /// Decodes the pixels available from the bytes fed so far
+    pub fn pixels(&mut self) -> impl Iterator<Item = Rgba> + '_ {
+        std::iter::from_fn(move || self.next_pixel())
+    }
+
+    fn next_pixel(&mut self) -> Option<Rgba> {
+        if self.remaining == 0 || self.error.is_some() {
+            return None;
+        }
+
+        let (buf, mut pos) = (&self.buf, self.pos);
+        let prev = self.state.px;
+        let result = self.state.next_pixel(|| {
+            let byte = buf.get(pos).copied().ok_or(QoiError::UnexpectedEof);
+            pos += 1;
+            byte
+        });
+
+        match result {
+            Ok(px) => {
+                self.pos = pos;
+                self.remaining -= 1;
+                Some(px)
+            }
+            Err(QoiError::UnexpectedEof) => {
+                // The opcode is incomplete; undo any partial update and retry after the next feed
+                self.state.px = prev;
+                None
+            }
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reusable buffers for decoding many images in a row, e.g. the frames of an animation. The
+/// color index, compressed data and output allocations are kept between calls to
+/// [`DecoderState::decode_into`], so decoding same-sized frames doesn't allocate. It is `Send`
+/// and `Sync`, so each thread of a pool can own one
+pub struct DecoderState {
+    state: OpcodeState,
+    body: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl DecoderState {
+    pub fn new() -> Self {
+        Self {
+            state: OpcodeState::new(),
+            body: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    /// Decodes an image, replacing the previous contents of [`DecoderState::data`]. Refuses
+    /// images larger than `DEFAULT_DECODE_LIMIT` bytes. On an error the data is left empty rather
+    /// than half overwritten, keeping its allocation. Returns (width, height)
+    pub fn decode_into<R: Read>(
+        &mut self,
+        reader: R,
+        channels: ChannelCount,
+    ) -> Result<(u32, u32)> {
+        let result = self.decode_image(reader, channels);
+        if result.is_err() {
+            self.out.clear();
+        }
+        result
+    }
+
+    fn decode_image<R: Read>(
+        &mut self,
+        mut reader: R,
+        channels: ChannelCount,
+    ) -> Result<(u32, u32)> {
+        let header = read_header(&mut reader)?;
+        check_channels(&header, channels)?;
+        let (width, height) = (header.width, header.height);
+
+        let len = uncompressed_len(width, height, channels);
+        if len > DEFAULT_DECODE_LIMIT {
+            return Err(QoiError::TooLarge {
+                required: len,
+                limit: DEFAULT_DECODE_LIMIT,
+            });
+        }
+
+        self.body.clear();
+        reader.take(header.body_len()).read_to_end(&mut self.body)?;
+
+        // Every byte is overwritten by the decode, so there is no need to clear first
+        self.out.resize(len, 0);
+        self.state.reset();
+
+        decode_body_slice(
+            &self.body,
+            &header,
+            channels,
+            PixelOrder::Rgba,
+            &mut self.out,
+            &mut self.state,
+        )?;
+
+        Ok((width, height))
+    }
+
+    /// The image data from the last `decode_into`, or an empty slice if it failed
+    pub fn data(&self) -> &[u8] {
+        &self.out
+    }
+}
+
+impl Default for DecoderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The stateful types hold only plain data, so they can be moved to worker threads, e.g. one
+// `DecoderState` per thread of a pool. `PixelIter` and `LineEncoder` follow their reader or writer
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Decoder>();
+    assert_send_sync::<DecoderState>();
+    assert_send_sync::<PixelIter<&[u8]>>();
+    assert_send_sync::<LineEncoder<Vec<u8>>>();
+    assert_send_sync::<EncodeOptions>();
+    assert_send_sync::<DecodeOptions>();
+    assert_send_sync::<QoiError>();
+};
+
+/// Entry point for `cargo fuzz`. Runs `data` through every decoder, which must not panic or hang,
+/// and checks that they agree on any stream which decodes successfully
+#[cfg(fuzzing)]
+pub fn fuzz_decode(data: &[u8]) {
+    // Keep allocations well within the fuzzer's memory limit
+    const FUZZ_DECODE_LIMIT: usize = 16 << 20;
+
+    let Ok(header) = read_header(data) else {
+        return;
+    };
+    if uncompressed_len(header.width, header.height, ChannelCount::Rgba) > FUZZ_DECODE_LIMIT {
+        return;
+    }
+
+    for output in [ChannelCount::Gray, ChannelCount::Rgb, ChannelCount::Rgba] {
+        let _ = decode_converted(data, output);
+    }
+
+    let channels = header.channels;
+    let decoded = decode_with_limit(data, channels, FUZZ_DECODE_LIMIT);
+    assert_eq!(verify(data).is_ok(), decoded.is_ok());
+    let Ok((image, _, _)) = decoded else {
+        return;
+    };
+    assert_eq!(decode_slice(data, channels).unwrap().0, image);
+    if let Ok((strict, _, _)) = decode_strict(data, channels) {
+        assert_eq!(strict, image);
+    }
+    assert_eq!(verify(data).unwrap(), (header.width, header.height));
+    let region = decode_region(data, channels, 0, 0, header.width, header.height);
+    assert_eq!(region.unwrap(), image);
+    let partial = decode_partial(data, channels).unwrap();
+    assert!(partial.error.is_none());
+    assert_eq!(partial.data, image);
+
+    let pixels: Vec<Rgba> = decode_pixels(data).unwrap().map(Result::unwrap).collect();
+    assert_eq!(drop_channels(&pixels.concat(), channels), image);
+
+    let planar = decode_planar(data, channels).unwrap();
+    for (i, &[r, g, b, a]) in pixels.iter().enumerate() {
+        assert_eq!([planar.red[i], planar.green[i], planar.blue[i]], [r, g, b]);
+        assert_eq!(
+            planar.alpha.as_ref().map(|alpha| alpha[i]),
+            (channels == ChannelCount::Rgba).then_some(a)
+        );
+    }
+
+    let mut decoder = Decoder::new();
+    let mut pushed = Vec::new();
+    for chunk in data.chunks(7) {
+        decoder.feed(chunk).unwrap();
+        pushed.extend(decoder.pixels());
+    }
+    assert!(decoder.is_finished() && decoder.error().is_none());
+    assert_eq!(pushed, pixels);
+}
+
+/// Allocates the output buffer, unless it would exceed `max_bytes`
+pub(crate) fn alloc_output(len: usize, max_bytes: usize) -> Result<Vec<u8>> {
+    if len > max_bytes {
+        return Err(QoiError::TooLarge {
+            required: len,
+            limit: max_bytes,
+        });
+    }
+    Ok(vec![0; len])
+}
+
+/// Fails with `ChannelMismatch` unless `channels` is the channel count stored in the header, so
+/// that e.g. an RGBA image isn't misread as RGB with every pixel after the first shifted
+pub(crate) fn check_channels(header: &QoiHeader, channels: ChannelCount) -> Result<()> {
+    if header.channels != channels {
+        return Err(QoiError::ChannelMismatch {
+            expected: header.channels,
+            requested: channels,
+        });
+    }
+    Ok(())
+}
+
+/// Uncompressed image data length in bytes
+pub(crate) fn uncompressed_len(width: u32, height: u32, channels: ChannelCount) -> usize {
+    // Saturates rather than wrapping where usize is 32 bits, so that the allocation limit still
+    // refuses the image
+    (width as usize)
+        .saturating_mul(height as usize)
+        .saturating_mul(channels.bytes_per_pixel())
+}
+
+/// Decodes the compressed image data following the header into `out_buf`, checking that it
+/// ends with the padding and occupies exactly the length declared in the header
+fn decode_body<R: Read>(
+    reader: R,
+    header: &QoiHeader,
+    channels: ChannelCount,
+    order: PixelOrder,
+    out_buf: &mut [u8],
+) -> Result<()> {
+    let body = read_body(reader, header.body_len())?;
+    decode_body_slice(
+        &body,
+        header,
+        channels,
+        order,
+        out_buf,
+        &mut OpcodeState::new(),
+    )
+}
+
+/// Like `decode_body`, but for compressed data which is already in memory, starting from `state`.
+/// `body` may extend past the declared length
+fn decode_body_slice(
+    body: &[u8],
+    header: &QoiHeader,
+    channels: ChannelCount,
+    order: PixelOrder,
+    out_buf: &mut [u8],
+    state: &mut OpcodeState,
+) -> Result<()> {
+    // Check the CRC first, so that corruption is reported as such rather than as a bad opcode
+    check_crc(body, header)?;
+    state.configure(header);
+
+    let body = &body[..body.len().min(header.compressed_len as usize)];
+    let opcodes_len = decode_opcodes(body, channels, order, out_buf, state)?;
+
+    check_end(body, opcodes_len, header.compressed_len, state)
+}
+
+/// Verifies the CRC following the compressed data, if the header says there is one
+fn check_crc(body: &[u8], header: &QoiHeader) -> Result<()> {
+    if header.has_crc {
+        let len = header.compressed_len as usize;
+        let stored = body
+            .get(len..len + CRC_SIZE)
+            .ok_or(QoiError::UnexpectedEof)?;
+        let stored = u32::from_le_bytes(stored.try_into().unwrap());
+        let computed = crc32(0, &body[..len]);
+        if stored != computed {
+            return Err(QoiError::ChecksumMismatch { stored, computed });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the `opcodes_len` bytes of opcodes at the start of `body` are followed by the
+/// padding, that together they occupy exactly `compressed_len` bytes, and that `state` isn't left
+/// partway through a run which continues past the last pixel
+fn check_end(
+    body: &[u8],
+    opcodes_len: usize,
+    compressed_len: u32,
+    state: &OpcodeState,
+) -> Result<()> {
+    if state.run > 0 {
+        return Err(QoiError::RunPastEnd);
+    }
+
+    let padding = body
+        .get(opcodes_len..opcodes_len + QOI_PADDING)
+        .ok_or(QoiError::UnexpectedEof)?;
+    if padding.iter().any(|&b| b != 0) {
+        return Err(QoiError::BadPadding);
+    }
+
+    let consumed = opcodes_len + QOI_PADDING;
+    if consumed != compressed_len as usize {
+        return Err(QoiError::SizeMismatch {
+            declared: compressed_len,
+            actual: consumed,
+        });
+    }
+
+    Ok(())
+}
+
+/// Reads the compressed image data following the header in one go. The buffer grows as data
+/// arrives rather than trusting the header's length up front
+fn read_body<R: Read>(reader: R, len: u64) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader.take(len).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Decodes the opcodes in `data` until `out_buf` is filled, and returns the number of bytes consumed
+fn decode_opcodes(
+    data: &[u8],
+    channels: ChannelCount,
+    order: PixelOrder,
+    out_buf: &mut [u8],
+    state: &mut OpcodeState,
+) -> Result<usize> {
+    match channels {
+        ChannelCount::Gray => decode_opcodes_n::<1>(data, order, out_buf, state),
+        ChannelCount::Rgb => decode_opcodes_n::<3>(data, order, out_buf, state),
+        ChannelCount::Rgba => decode_opcodes_n::<4>(data, order, out_buf, state),
+    }
+}
+
+/// `decode_opcodes` specialized for `N` bytes per output pixel
+fn decode_opcodes_n<const N: usize>(
+    data: &[u8],
+    order: PixelOrder,
+    out_buf: &mut [u8],
+    state: &mut OpcodeState,
+) -> Result<usize> {
+    let mut bytes = data.iter().copied();
+    let mut read_byte = || bytes.next().ok_or(QoiError::UnexpectedEof);
+
+    for out_px in out_buf.chunks_exact_mut(N) {
+        let mut px = state.next_pixel(&mut read_byte)?;
+        if order == PixelOrder::Bgra {
+            px.swap(0, 2);
+        }
+        out_px.copy_from_slice(&px[..N]);
+    }
+
+    Ok(data.len() - bytes.len())
+}
+
+/// The decoder's state carried from one pixel to the next
+struct OpcodeState {
+    run: u32,          // Run length encoding run length
+    px: Rgba,          // Previous pixel
+    index: ColorIndex, // Recently seen pixels
+    strict: bool,      // Whether to reject references to unwritten index slots
+    row_width: u32,    // Pixels between resets of `px`, or 0 to never reset it
+    col: u32,          // Position of the next pixel within its row
+}
+
+impl OpcodeState {
+    fn new() -> Self {
+        Self {
+            run: 0,
+            px: DEFAULT_PREV_PIXEL,
+            index: ColorIndex::new(),
+            strict: false,
+            row_width: 0,
+            col: 0,
+        }
+    }
+
+    /// Applies the header flags which change how the opcodes decode
+    fn configure(&mut self, header: &QoiHeader) {
+        self.row_width = if header.resets_rows { header.width } else { 0 };
+    }
+
+    /// A state which fails with `UnwrittenIndex` rather than reading a slot no pixel has filled
+    fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::new()
+        }
+    }
+
+    /// Returns to the state at the start of a stream
+    fn reset(&mut self) {
+        *self = Self {
+            strict: self.strict,
+            ..Self::new()
+        };
+    }
+
+    /// Decodes the next pixel, pulling opcode bytes from `read_byte` as needed. Every call yields
+    /// exactly one pixel and reads at least one byte unless a run is in progress, so a loop over the
+    /// output always terminates
+    fn next_pixel(&mut self, read_byte: impl FnMut() -> Result<u8>) -> Result<Rgba> {
+        if self.row_width == 0 {
+            return self.next_opcode_pixel(read_byte);
+        }
+
+        if self.col == 0 {
+            if self.run > 0 {
+                return Err(QoiError::RunAcrossRows);
+            }
+            self.px = DEFAULT_PREV_PIXEL;
+        }
+        let px = self.next_opcode_pixel(read_byte)?;
+        self.col = (self.col + 1) % self.row_width;
+
+        Ok(px)
+    }
+
+    /// `next_pixel` without the row resets
+    fn next_opcode_pixel(&mut self, mut read_byte: impl FnMut() -> Result<u8>) -> Result<Rgba> {
+        let Self {
+            run,
+            px,
+            index,
+            strict,
+            ..
+        } = self;
+
+        if *run > 0 {
+            *run -= 1;
+            return Ok(*px);
+        }
+
+        let b1 = read_byte()?;
+
+        if (b1 & QOI_MASK_2) == QOI_INDEX {
+            let slot = b1 ^ QOI_INDEX;
+            *px = index.get(slot);
+            // Every pixel is stored in the slot of its hash. An unwritten slot holds zero, which only
+            // hashes to slot 0, where it stands for transparent black as in the encoder
+            if *strict && ColorIndex::slot(*px) != slot {
+                return Err(QoiError::UnwrittenIndex(slot));
+            }
+        } else if (b1 & QOI_MASK_3) == QOI_RUN_8 {
+            *run = (b1 & 0x1f) as u32;
+        } else if (b1 & QOI_MASK_3) == QOI_RUN_16 {
+            let b2 = read_byte()?;
+            *run = ((((b1 & 0x1f) as u32) << 8) | (b2 as u32)) + 32;
+        } else if (b1 & QOI_MASK_2) == QOI_DIFF_8 {
+            px[0] = px[0].wrapping_add_signed(((b1 >> 4) & 0x03) as i8 - 1);
+            px[1] = px[1].wrapping_add_signed(((b1 >> 2) & 0x03) as i8 - 1);
+            px[2] = px[2].wrapping_add_signed((b1 & 0x03) as i8 - 1);
+        } else if (b1 & QOI_MASK_3) == QOI_DIFF_16 {
+            let b2 = read_byte()?;
+            px[0] = px[0].wrapping_add_signed((b1 & 0x1f) as i8 - 15);
+            px[1] = px[1].wrapping_add_signed((b2 >> 4) as i8 - 7);
+            px[2] = px[2].wrapping_add_signed((b2 & 0x0f) as i8 - 7);
+        } else if (b1 & QOI_MASK_4) == QOI_DIFF_24 {
+            let b2 = read_byte()?;
+            let b3 = read_byte()?;
+            px[0] = px[0].wrapping_add_signed((((b1 & 0x0f) << 1) | (b2 >> 7)) as i8 - 15);
+            px[1] = px[1].wrapping_add_signed(((b2 & 0x7c) >> 2) as i8 - 15);
+            px[2] = px[2].wrapping_add_signed((((b2 & 0x03) << 3) | ((b3 & 0xe0) >> 5)) as i8 - 15);
+            px[3] = px[3].wrapping_add_signed((b3 & 0x1f) as i8 - 15);
+        } else if (b1 & QOI_MASK_4) == QOI_COLOR {
+            if b1 & 8 != 0 {
+                px[0] = read_byte()?;
+            }
+            if b1 & 4 != 0 {
+                px[1] = read_byte()?;
+            }
+            if b1 & 2 != 0 {
+                px[2] = read_byte()?;
+            }
+            if b1 & 1 != 0 {
+                px[3] = read_byte()?;
+            }
+        } else {
+            // The masks above cover every byte, so this only guards against future edits
+            return Err(QoiError::InvalidOpcode(b1));
+        }
+
+        index.insert(*px);
+
+        Ok(*px)
+    }
+}
@@ -0,0 +1,100 @@
+//! Key/value metadata, e.g. the source file or the tool which produced an image, stored in a
+//! trailer after the end of a stream.
+//!
+//! The stream itself is unchanged, and every decoder stops at the end of it, so files with a
+//! trailer decode as usual:
+//!
+//! | Field        | Size                    |
+//! |--------------|-------------------------|
+//! | Stream       | `QoiHeader::stream_len` |
+//! | Magic `qoim` | 4                       |
+//! | Entries size | 4 (LE)                  |
+//! | Entries      | Entries size            |
+//!
+//! Each entry is a key length (4 bytes, LE), the key as UTF-8, a value length (4 bytes, LE) and
+//! the value
+use crate::{encode, read_header, ChannelCount, QoiError, Result};
+use std::io::{self, Read, Seek, Write};
+
+const METADATA_MAGIC: &[u8; 4] = b"qoim";
+
+/// Encodes the image followed by a trailer holding `metadata`, in order. Returns the total number
+/// of bytes written
+pub fn encode_with_metadata<W: Write + Seek>(
+    mut writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+    metadata: &[(String, Vec<u8>)],
+) -> Result<u64> {
+    let mut entries = Vec::new();
+    for (key, value) in metadata {
+        for field in [key.as_bytes(), value] {
+            let len = u32::try_from(field.len()).map_err(|_| QoiError::InvalidMetadata)?;
+            entries.extend_from_slice(&len.to_le_bytes());
+            entries.extend_from_slice(field);
+        }
+    }
+    let entries_len = u32::try_from(entries.len()).map_err(|_| QoiError::InvalidMetadata)?;
+
+    let stream_len = encode(&mut writer, data, width, channels)?;
+    writer.write_all(METADATA_MAGIC)?;
+    writer.write_all(&entries_len.to_le_bytes())?;
+    writer.write_all(&entries)?;
+
+    Ok(stream_len + (METADATA_MAGIC.len() + 4 + entries.len()) as u64)
+}
+
+/// Reads the metadata following a stream, skipping over the image without decoding it. Returns
+/// an empty list if the stream isn't followed by a metadata trailer
+pub fn read_metadata<R: Read>(mut reader: R) -> Result<Vec<(String, Vec<u8>)>> {
+    let header = read_header(&mut reader)?;
+    let skipped = io::copy(&mut (&mut reader).take(header.body_len()), &mut io::sink())?;
+    if skipped != header.body_len() {
+        return Err(QoiError::UnexpectedEof);
+    }
+
+    let mut magic = [0; 4];
+    match reader.read_exact(&mut magic) {
+        Ok(()) if &magic == METADATA_MAGIC => {}
+        // Nothing follows the stream, or something other than metadata does
+        Ok(()) => return Ok(Vec::new()),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut len_buf = [0; 4];
+    reader.read_exact(&mut len_buf)?;
+    let entries_len = u32::from_le_bytes(len_buf) as u64;
+
+    // Grow the buffer as data arrives rather than trusting the length up front
+    let mut entries = Vec::new();
+    reader.take(entries_len).read_to_end(&mut entries)?;
+    if entries.len() as u64 != entries_len {
+        return Err(QoiError::UnexpectedEof);
+    }
+
+    let mut rest = &entries[..];
+    let mut metadata = Vec::new();
+    while !rest.is_empty() {
+        let key = split_field(&mut rest)?;
+        let key = std::str::from_utf8(key).map_err(|_| QoiError::InvalidMetadata)?;
+        let value = split_field(&mut rest)?;
+        metadata.push((key.to_string(), value.to_vec()));
+    }
+
+    Ok(metadata)
+}
+
+/// Splits a length-prefixed field off the front of `entries`
+fn split_field<'a>(entries: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let (len, tail) = entries
+        .split_first_chunk::<4>()
+        .ok_or(QoiError::InvalidMetadata)?;
+    let len = u32::from_le_bytes(*len) as usize;
+    let (field, tail) = tail
+        .split_at_checked(len)
+        .ok_or(QoiError::InvalidMetadata)?;
+    *entries = tail;
+    Ok(field)
+}
@@ -0,0 +1,22 @@
+//! Decoding straight from a memory-mapped file, for large images
+use crate::{decode_slice, ChannelCount, Result};
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::Path;
+
+/// Like `read_from_file`, but maps the file into memory and decodes from the mapping rather than
+/// reading it through a buffer, so the compressed data is never copied and the OS pages it in as
+/// the decoder reaches it. Returns (image data, width, height)
+///
+/// # Safety
+/// The file must not be modified or truncated, by this or any other process, until the function
+/// returns. The mapping would change under the decoder, and reading a truncated mapping raises
+/// `SIGBUS` on Unix
+pub unsafe fn read_from_file_mmap(
+    path: impl AsRef<Path>,
+    channels: ChannelCount,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let file = File::open(path)?;
+    let map = Mmap::map(&file)?;
+    decode_slice(&map, channels)
+}
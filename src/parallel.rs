@@ -0,0 +1,140 @@
+//! Multithreaded encoding and decoding by splitting the image into horizontal bands.
+//!
+//! This is NOT a standard QOI file. It is a container around independently encoded bands,
+//! each of which is a complete QOI stream with its own header:
+//!
+//! | Field        | Size                 |
+//! |--------------|----------------------|
+//! | Magic `qoip` | 4                    |
+//! | Version      | 1                    |
+//! | Width        | 4 (LE)               |
+//! | Height       | 4 (LE)               |
+//! | Channels     | 1                    |
+//! | Band count   | 4 (LE)               |
+//! | Band lengths | 4 (LE) per band      |
+//! | Bands        | Sum of band lengths  |
+//!
+//! Every band is `PARALLEL_BAND_HEIGHT` rows tall, except for the last which holds the remainder.
+//! Use `is_parallel_container` to tell these apart from single QOI streams.
+use crate::{
+    alloc_output, check_dimensions, decode_to_slice, encode_to_vec, size_field, uncompressed_len,
+    verify_and_calculate_dims, ChannelCount, QoiError, Result, DEFAULT_DECODE_LIMIT,
+};
+use rayon::prelude::*;
+use std::io::Write;
+
+const PARALLEL_MAGIC: &[u8; 4] = b"qoip";
+/// Version of the container layout. It only changes with the table above, never with the bands'
+/// own headers
+const PARALLEL_VERSION: u8 = 1;
+/// Rows of pixels encoded in each band
+const PARALLEL_BAND_HEIGHT: usize = 64;
+/// Length of the container header, excluding the band lengths
+const CONTAINER_HEADER_SIZE: usize = 18;
+
+/// Returns true if `data` begins with a multi-band container rather than a single QOI stream
+pub fn is_parallel_container(data: &[u8]) -> bool {
+    data.starts_with(PARALLEL_MAGIC)
+}
+
+/// Encodes horizontal bands of the image in parallel, writing a multi-band container. Returns the
+/// total number of bytes written
+pub fn encode_parallel<W: Write>(
+    mut writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+) -> Result<usize> {
+    let (width, height, _) = verify_and_calculate_dims(data, width, channels)?;
+
+    let band_len = PARALLEL_BAND_HEIGHT * width as usize * channels as usize;
+    let bands = data
+        .par_chunks(band_len)
+        .map(|band| encode_to_vec(band, width as usize, channels))
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+
+    writer.write_all(PARALLEL_MAGIC)?;
+    writer.write_all(&[PARALLEL_VERSION])?;
+    writer.write_all(&width.to_le_bytes())?;
+    writer.write_all(&height.to_le_bytes())?;
+    writer.write_all(&[channels as u8])?;
+    writer.write_all(&(bands.len() as u32).to_le_bytes())?;
+    for band in &bands {
+        writer.write_all(&size_field(band.len())?.to_le_bytes())?;
+    }
+    for band in &bands {
+        writer.write_all(band)?;
+    }
+
+    let bands_len: usize = bands.iter().map(Vec::len).sum();
+    Ok(CONTAINER_HEADER_SIZE + 4 * bands.len() + bands_len)
+}
+
+/// Decodes a multi-band container in parallel. Returns (image data, width, height)
+pub fn decode_parallel(data: &[u8], channels: ChannelCount) -> Result<(Vec<u8>, u32, u32)> {
+    let header = data
+        .get(..CONTAINER_HEADER_SIZE)
+        .ok_or(QoiError::UnexpectedEof)?;
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+
+    let magic: [u8; 4] = header[..4].try_into().unwrap();
+    if &magic != PARALLEL_MAGIC {
+        return Err(QoiError::BadMagic(magic));
+    }
+    if header[4] != PARALLEL_VERSION {
+        return Err(QoiError::UnsupportedVersion(header[4]));
+    }
+    let width = read_u32(5);
+    let height = read_u32(9);
+    let stored_channels = ChannelCount::try_from(header[13])?;
+    let band_count = read_u32(14) as usize;
+
+    check_dimensions(width, height)?;
+    if stored_channels != channels {
+        return Err(QoiError::ChannelMismatch {
+            expected: stored_channels,
+            requested: channels,
+        });
+    }
+    if band_count != (height as usize).div_ceil(PARALLEL_BAND_HEIGHT) {
+        return Err(QoiError::InvalidContainer);
+    }
+
+    // Split the remaining data into bands
+    let lengths_end = CONTAINER_HEADER_SIZE + band_count * 4;
+    let lengths = data
+        .get(CONTAINER_HEADER_SIZE..lengths_end)
+        .ok_or(QoiError::UnexpectedEof)?;
+    let mut rest = &data[lengths_end..];
+    let mut bands = Vec::with_capacity(band_count);
+    for len in lengths.chunks_exact(4) {
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        if rest.len() < len {
+            return Err(QoiError::UnexpectedEof);
+        }
+        let (band, tail) = rest.split_at(len);
+        bands.push(band);
+        rest = tail;
+    }
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+    let band_len = PARALLEL_BAND_HEIGHT * width as usize * channels as usize;
+
+    out_buf
+        .par_chunks_mut(band_len)
+        .zip(bands)
+        .try_for_each(|(out, band)| {
+            let band_height = out.len() / (width as usize * channels as usize);
+            let dims = decode_to_slice(band, channels, out)?;
+            if dims != (width, band_height as u32) {
+                return Err(QoiError::InvalidContainer);
+            }
+            Ok(())
+        })?;
+
+    Ok((out_buf, width, height))
+}
@@ -0,0 +1,129 @@
+//! Conversions between PNG and QOI files, as done by the `qoiconv` example
+use crate::{
+    decode16_auto, decode_auto, decode_converted, encode16_to_vec, encode_to_vec, is_qoi16,
+    ChannelCount, QoiError, Result,
+};
+use png::{BitDepth, ColorType, Transformations};
+use std::io;
+
+impl From<png::DecodingError> for QoiError {
+    fn from(err: png::DecodingError) -> Self {
+        match err {
+            png::DecodingError::IoError(err) => err.into(),
+            other => QoiError::Io(io::Error::new(io::ErrorKind::InvalidData, other)),
+        }
+    }
+}
+
+impl From<png::EncodingError> for QoiError {
+    fn from(err: png::EncodingError) -> Self {
+        match err {
+            png::EncodingError::IoError(err) => err.into(),
+            other => QoiError::Io(io::Error::other(other)),
+        }
+    }
+}
+
+/// Converts a PNG file to QOI. Palette and low bit depth images are expanded to 8 bits, and
+/// grayscale with alpha to RGBA. 16-bit images become 16-bit streams, see `encode16_to_vec`
+pub fn png_to_qoi(png: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let bytes_per_sample = match info.bit_depth {
+        BitDepth::Eight => 1,
+        BitDepth::Sixteen => 2,
+        depth => return Err(QoiError::UnsupportedBitDepth(depth as u8)),
+    };
+    let channels = match info.color_type {
+        ColorType::Grayscale => ChannelCount::Gray,
+        ColorType::Rgb => ChannelCount::Rgb,
+        ColorType::Rgba | ColorType::GrayscaleAlpha => ChannelCount::Rgba,
+        ColorType::Indexed => unreachable!("EXPAND turns palettes into RGB or RGBA"),
+    };
+    if info.color_type == ColorType::GrayscaleAlpha {
+        buf = buf
+            .chunks_exact(2 * bytes_per_sample)
+            .flat_map(|px| {
+                let (gray, alpha) = px.split_at(bytes_per_sample);
+                [gray, gray, gray, alpha].concat()
+            })
+            .collect();
+    }
+
+    let width = info.width as usize;
+    if bytes_per_sample == 2 {
+        // PNG stores 16-bit samples big endian
+        let samples: Vec<u16> = buf
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        encode16_to_vec(&samples, width, channels)
+    } else {
+        encode_to_vec(&buf, width, channels)
+    }
+}
+
+/// Converts a QOI file, 8 or 16-bit, to a PNG file with `channels`, or with the channel count
+/// stored in the header if `None`
+pub fn qoi_to_png(qoi: &[u8], channels: Option<ChannelCount>) -> Result<Vec<u8>> {
+    let (data, width, height, channels, depth) = if is_qoi16(qoi) {
+        // `decode16` only decodes to the stored channel count, so convert afterwards
+        let (samples, width, height, stored) = decode16_auto(qoi)?;
+        let channels = channels.unwrap_or(stored);
+        let data = convert16(&samples, stored, channels)
+            .iter()
+            .flat_map(|s| s.to_be_bytes())
+            .collect();
+        (data, width, height, channels, BitDepth::Sixteen)
+    } else {
+        let (data, width, height, channels) = match channels {
+            Some(channels) => {
+                let (data, width, height) = decode_converted(qoi, channels)?;
+                (data, width, height, channels)
+            }
+            None => decode_auto(qoi)?,
+        };
+        (data, width, height, channels, BitDepth::Eight)
+    };
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(match channels {
+        ChannelCount::Gray => ColorType::Grayscale,
+        ChannelCount::Rgb => ColorType::Rgb,
+        ChannelCount::Rgba => ColorType::Rgba,
+    });
+    encoder.set_depth(depth);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+
+    Ok(png)
+}
+
+/// Converts 16-bit samples from one channel count to another, the way `decode_converted` does for
+/// 8-bit pixels: gray is spread over red, green and blue, a missing alpha is opaque, and dropping
+/// channels keeps the first ones
+fn convert16(samples: &[u16], from: ChannelCount, to: ChannelCount) -> Vec<u16> {
+    if from == to {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(from as usize)
+        .flat_map(|px| {
+            let px = match *px {
+                [v] => [v, v, v, u16::MAX],
+                [r, g, b] => [r, g, b, u16::MAX],
+                [r, g, b, a] => [r, g, b, a],
+                _ => unreachable!(),
+            };
+            px.into_iter().take(to as usize)
+        })
+        .collect()
+}
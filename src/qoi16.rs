@@ -0,0 +1,340 @@
+//! A variant of the format with 16 bits per channel, for HDR textures and scientific images.
+//!
+//! It runs the same opcodes as the 8-bit format over `u16` channels, so it has its own magic
+//! number and 8-bit decoders reject it up front:
+//!
+//! | Field           | Size   |
+//! |-----------------|--------|
+//! | Magic `qo16`    | 4      |
+//! | Version         | 1      |
+//! | Width           | 4 (LE) |
+//! | Height          | 4 (LE) |
+//! | Channels        | 1      |
+//! | Compressed size | 4 (LE) |
+//! | Opcodes         |        |
+//! | Padding         | 4      |
+//!
+//! Differences from the 8-bit opcodes:
+//! * The `QOI_DIFF_*` opcodes add their signed differences to 16-bit channels, wrapping at 65536
+//! * `QOI_COLOR` is followed by 2 bytes (LE) for each channel it sets
+//! * The index hash XORs the channels together and then folds the high byte onto the low byte
+//! * The previous pixel starts out as opaque black, `[0, 0, 0, 0xFFFF]`
+//!
+//! Channel counts mean the same as in the 8-bit format, but each sample takes 2 bytes in memory
+use crate::{
+    calculate_dims, check_dimensions, read_body, size_field, ChannelCount, OutputBuffer, QoiError,
+    Result, COLOR_LUT_SIZE, DEFAULT_DECODE_LIMIT, MAX_RUN_8_LENGTH, MAX_RUN_LENGTH, QOI_COLOR,
+    QOI_DIFF_16, QOI_DIFF_24, QOI_DIFF_8, QOI_INDEX, QOI_MASK_2, QOI_MASK_3, QOI_MASK_4,
+    QOI_PADDING, QOI_RUN_16, QOI_RUN_8,
+};
+use std::io::{self, Read, Write};
+
+/// A single 16-bit pixel as red, green, blue, alpha
+pub type Rgba16 = [u16; 4];
+
+const MAGIC_16: &[u8; 4] = b"qo16";
+/// Version of the 16-bit header layout
+const FORMAT_VERSION_16: u8 = 1;
+const HEADER_SIZE_16: usize = 18;
+const SIZE_FIELD_OFFSET_16: usize = 14;
+/// The pixel decoded if the first pixel is an RLE command
+const DEFAULT_PREV_PIXEL_16: Rgba16 = [0, 0, 0, 0xFFFF];
+
+/// Hash of a 16-bit pixel, which modulo the index size gives its position in the color index
+fn color_hash_16([r, g, b, a]: Rgba16) -> u8 {
+    let hash = r ^ g ^ b ^ a;
+    (hash ^ (hash >> 8)) as u8
+}
+
+/// Encodes 16-bit samples into a new buffer
+pub fn encode16_to_vec(data: &[u16], width: usize, channels: ChannelCount) -> Result<Vec<u8>> {
+    let (width, height, _) = calculate_dims(data.len(), width, channels)?;
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC_16);
+    buf.push(FORMAT_VERSION_16);
+    buf.extend_from_slice(&width.to_le_bytes());
+    buf.extend_from_slice(&height.to_le_bytes());
+    buf.push(channels as u8);
+    buf.extend_from_slice(&0u32.to_le_bytes());
+
+    let image_data_len =
+        encode16_pixels(&mut buf, data, channels).expect("Writing to a Vec cannot fail");
+
+    // Backpatch the length in the header
+    buf[SIZE_FIELD_OFFSET_16..SIZE_FIELD_OFFSET_16 + 4]
+        .copy_from_slice(&size_field(image_data_len)?.to_le_bytes());
+
+    Ok(buf)
+}
+
+/// Writes the opcodes for the pixels followed by the padding, and returns the number of bytes written
+fn encode16_pixels<W: Write>(writer: W, data: &[u16], channels: ChannelCount) -> io::Result<usize> {
+    let mut out = OutputBuffer::new(writer);
+    let mut state = EncoderState16::new();
+
+    for pixel_data in data.chunks_exact(channels as usize) {
+        let px = match channels {
+            ChannelCount::Gray => [pixel_data[0], pixel_data[0], pixel_data[0], 0xFFFF],
+            ChannelCount::Rgb => [pixel_data[0], pixel_data[1], pixel_data[2], 0xFFFF],
+            ChannelCount::Rgba => [pixel_data[0], pixel_data[1], pixel_data[2], pixel_data[3]],
+        };
+        state.push(px, &mut out)?;
+    }
+
+    // Dump any current run
+    state.flush_run(&mut out)?;
+
+    // Padding
+    out.push(&[0; QOI_PADDING])?;
+
+    out.finish()
+}
+
+/// Returns (samples, width, height). Refuses images larger than `DEFAULT_DECODE_LIMIT` bytes
+pub fn decode16<R: Read>(reader: R, channels: ChannelCount) -> Result<(Vec<u16>, u32, u32)> {
+    let (data, width, height, _) = decode16_with_channels(reader, Some(channels))?;
+    Ok((data, width, height))
+}
+
+/// Decodes using the channel count stored in the file. Returns (samples, width, height, channels)
+pub fn decode16_auto<R: Read>(reader: R) -> Result<(Vec<u16>, u32, u32, ChannelCount)> {
+    decode16_with_channels(reader, None)
+}
+
+/// Returns true if `data` begins with a 16-bit stream
+pub fn is_qoi16(data: &[u8]) -> bool {
+    data.starts_with(MAGIC_16)
+}
+
+/// Decodes to `channels`, or to the channel count stored in the header if `None`
+fn decode16_with_channels<R: Read>(
+    mut reader: R,
+    channels: Option<ChannelCount>,
+) -> Result<(Vec<u16>, u32, u32, ChannelCount)> {
+    let mut header = [0u8; HEADER_SIZE_16];
+    reader.read_exact(&mut header)?;
+
+    let magic: [u8; 4] = header[..4].try_into().unwrap();
+    if &magic != MAGIC_16 {
+        return Err(QoiError::BadMagic(magic));
+    }
+    if header[4] != FORMAT_VERSION_16 {
+        return Err(QoiError::UnsupportedVersion(header[4]));
+    }
+
+    let read_u32 = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    let (width, height) = (read_u32(5), read_u32(9));
+    check_dimensions(width, height)?;
+    let stored_channels = ChannelCount::try_from(header[13])?;
+    let compressed_len = read_u32(SIZE_FIELD_OFFSET_16);
+
+    let channels = channels.unwrap_or(stored_channels);
+    if channels != stored_channels {
+        return Err(QoiError::ChannelMismatch {
+            expected: stored_channels,
+            requested: channels,
+        });
+    }
+    // Saturates like `uncompressed_len`, so that the limit still refuses the image where usize is
+    // 32 bits
+    let samples = (width as usize)
+        .saturating_mul(height as usize)
+        .saturating_mul(channels as usize);
+    let required = samples.saturating_mul(2);
+    if required > DEFAULT_DECODE_LIMIT {
+        return Err(QoiError::TooLarge {
+            required,
+            limit: DEFAULT_DECODE_LIMIT,
+        });
+    }
+
+    let body = read_body(reader, compressed_len as u64)?;
+    let mut out_buf = vec![0u16; samples];
+
+    let mut state = OpcodeState16::new();
+    let mut bytes = body.iter().copied();
+    let mut read_byte = || bytes.next().ok_or(QoiError::UnexpectedEof);
+    for out_px in out_buf.chunks_exact_mut(channels as usize) {
+        let px = state.next_pixel(&mut read_byte)?;
+        out_px.copy_from_slice(&px[..channels as usize]);
+    }
+
+    let opcodes_len = body.len() - bytes.len();
+    if state.run > 0 {
+        return Err(QoiError::RunPastEnd);
+    }
+    let padding = body
+        .get(opcodes_len..opcodes_len + QOI_PADDING)
+        .ok_or(QoiError::UnexpectedEof)?;
+    if padding.iter().any(|&b| b != 0) {
+        return Err(QoiError::BadPadding);
+    }
+    let consumed = opcodes_len + QOI_PADDING;
+    if consumed != compressed_len as usize {
+        return Err(QoiError::SizeMismatch {
+            declared: compressed_len,
+            actual: consumed,
+        });
+    }
+
+    Ok((out_buf, width, height, channels))
+}
+
+/// The 16-bit encoder's state carried from one pixel to the next
+struct EncoderState16 {
+    run: u32,                        // Run length encoding run length
+    px_prev: Rgba16,                 // Previous pixel
+    index: [Rgba16; COLOR_LUT_SIZE], // Recently seen pixels
+}
+
+impl EncoderState16 {
+    fn new() -> Self {
+        Self {
+            run: 0,
+            px_prev: DEFAULT_PREV_PIXEL_16,
+            index: [[0; 4]; COLOR_LUT_SIZE],
+        }
+    }
+
+    /// Writes out the current run, if there is one
+    fn flush_run<W: Write>(&mut self, out: &mut OutputBuffer<W>) -> io::Result<()> {
+        if self.run == 0 {
+            return Ok(());
+        }
+
+        if self.run < MAX_RUN_8_LENGTH {
+            let run = self.run - 1;
+            out.push(&[QOI_RUN_8 | run as u8])?;
+        } else {
+            let run = self.run - MAX_RUN_8_LENGTH;
+            out.push(&[QOI_RUN_16 | (run >> 8) as u8, run as u8])?;
+        }
+        self.run = 0;
+
+        Ok(())
+    }
+
+    /// Encodes the next pixel
+    fn push<W: Write>(&mut self, px: Rgba16, out: &mut OutputBuffer<W>) -> io::Result<()> {
+        if px == self.px_prev {
+            self.run += 1;
+            if self.run == MAX_RUN_LENGTH {
+                self.flush_run(out)?;
+            }
+            return Ok(());
+        }
+
+        self.flush_run(out)?;
+
+        let index_pos = color_hash_16(px) % COLOR_LUT_SIZE as u8;
+
+        if px == self.index[index_pos as usize] {
+            out.push(&[QOI_INDEX | index_pos])?;
+        } else {
+            self.index[index_pos as usize] = px;
+            let diff: [i32; 4] = std::array::from_fn(|i| px[i] as i32 - self.px_prev[i] as i32);
+            let [vr, vg, vb, va] = diff;
+
+            let within_small_diff = diff.into_iter().all(|v| v > -16 && v < 17);
+
+            if within_small_diff {
+                if va == 0 && vr > -2 && vr < 3 && vg > -2 && vg < 3 && vb > -2 && vb < 3 {
+                    out.push(&[QOI_DIFF_8 | (((vr + 1) << 4) | (vg + 1) << 2 | (vb + 1)) as u8])?;
+                } else if va == 0 && vr > -16 && vr < 17 && vg > -8 && vg < 9 && vb > -8 && vb < 9 {
+                    out.push(&[
+                        QOI_DIFF_16 | (vr + 15) as u8,
+                        (((vg + 7) << 4) | (vb + 7)) as u8,
+                    ])?;
+                } else {
+                    out.push(&[
+                        QOI_DIFF_24 | ((vr + 15) >> 1) as u8,
+                        (((vr + 15) << 7) | ((vg + 15) << 2) | ((vb + 15) >> 3)) as u8,
+                        (((vb + 15) << 5) | (va + 15)) as u8,
+                    ])?;
+                }
+            } else {
+                let gate = |v: i32, x: u8| if v != 0 { x } else { 0 };
+
+                out.push(&[QOI_COLOR | gate(vr, 8) | gate(vg, 4) | gate(vb, 2) | gate(va, 1)])?;
+
+                for (v, channel) in diff.into_iter().zip(px) {
+                    if v != 0 {
+                        out.push(&channel.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+
+        self.px_prev = px;
+
+        Ok(())
+    }
+}
+
+/// The 16-bit decoder's state carried from one pixel to the next
+struct OpcodeState16 {
+    run: u32,                        // Run length encoding run length
+    px: Rgba16,                      // Previous pixel
+    index: [Rgba16; COLOR_LUT_SIZE], // Recently seen pixels
+}
+
+impl OpcodeState16 {
+    fn new() -> Self {
+        Self {
+            run: 0,
+            px: DEFAULT_PREV_PIXEL_16,
+            index: [[0; 4]; COLOR_LUT_SIZE],
+        }
+    }
+
+    /// Decodes the next pixel, pulling opcode bytes from `read_byte` as needed
+    fn next_pixel(&mut self, mut read_byte: impl FnMut() -> Result<u8>) -> Result<Rgba16> {
+        let Self { run, px, index } = self;
+
+        if *run > 0 {
+            *run -= 1;
+            return Ok(*px);
+        }
+
+        let b1 = read_byte()?;
+
+        if (b1 & QOI_MASK_2) == QOI_INDEX {
+            *px = index[(b1 ^ QOI_INDEX) as usize];
+        } else if (b1 & QOI_MASK_3) == QOI_RUN_8 {
+            *run = (b1 & 0x1f) as u32;
+        } else if (b1 & QOI_MASK_3) == QOI_RUN_16 {
+            let b2 = read_byte()?;
+            *run = ((((b1 & 0x1f) as u32) << 8) | (b2 as u32)) + 32;
+        } else if (b1 & QOI_MASK_2) == QOI_DIFF_8 {
+            px[0] = px[0].wrapping_add_signed(((b1 >> 4) & 0x03) as i16 - 1);
+            px[1] = px[1].wrapping_add_signed(((b1 >> 2) & 0x03) as i16 - 1);
+            px[2] = px[2].wrapping_add_signed((b1 & 0x03) as i16 - 1);
+        } else if (b1 & QOI_MASK_3) == QOI_DIFF_16 {
+            let b2 = read_byte()?;
+            px[0] = px[0].wrapping_add_signed((b1 & 0x1f) as i16 - 15);
+            px[1] = px[1].wrapping_add_signed((b2 >> 4) as i16 - 7);
+            px[2] = px[2].wrapping_add_signed((b2 & 0x0f) as i16 - 7);
+        } else if (b1 & QOI_MASK_4) == QOI_DIFF_24 {
+            let b2 = read_byte()?;
+            let b3 = read_byte()?;
+            px[0] = px[0].wrapping_add_signed((((b1 & 0x0f) << 1) | (b2 >> 7)) as i16 - 15);
+            px[1] = px[1].wrapping_add_signed(((b2 & 0x7c) >> 2) as i16 - 15);
+            px[2] =
+                px[2].wrapping_add_signed((((b2 & 0x03) << 3) | ((b3 & 0xe0) >> 5)) as i16 - 15);
+            px[3] = px[3].wrapping_add_signed((b3 & 0x1f) as i16 - 15);
+        } else if (b1 & QOI_MASK_4) == QOI_COLOR {
+            for (bit, channel) in [8, 4, 2, 1].into_iter().zip(px.iter_mut()) {
+                if b1 & bit != 0 {
+                    *channel = u16::from_le_bytes([read_byte()?, read_byte()?]);
+                }
+            }
+        } else {
+            return Err(QoiError::InvalidOpcode(b1));
+        }
+
+        index[(color_hash_16(*px) % COLOR_LUT_SIZE as u8) as usize] = *px;
+
+        Ok(*px)
+    }
+}
@@ -0,0 +1,58 @@
+//! Async decoding and encoding over `tokio`'s I/O traits. Only the I/O is async: the compressed
+//! data is buffered in memory and run through the same opcode decoder and encoder as `decode` and
+//! `encode_to_vec`
+use crate::{
+    alloc_output, check_channels, decode_body_slice, encode_to_vec, read_header, uncompressed_len,
+    ChannelCount, OpcodeState, PixelOrder, Result, DEFAULT_DECODE_LIMIT, HEADER_SIZE,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Like `decode`, reading from an async reader. Refuses images larger than `DEFAULT_DECODE_LIMIT`
+/// bytes. Returns (image data, width, height)
+pub async fn decode_async<R: AsyncRead + Unpin>(
+    mut reader: R,
+    channels: ChannelCount,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let mut header_buf = [0; HEADER_SIZE];
+    reader.read_exact(&mut header_buf).await?;
+    let header = read_header(&header_buf[..])?;
+    check_channels(&header, channels)?;
+    let (width, height) = (header.width, header.height);
+
+    let mut out_buf = alloc_output(
+        uncompressed_len(width, height, channels),
+        DEFAULT_DECODE_LIMIT,
+    )?;
+
+    let mut body = Vec::new();
+    reader
+        .take(header.body_len())
+        .read_to_end(&mut body)
+        .await?;
+
+    decode_body_slice(
+        &body,
+        &header,
+        channels,
+        PixelOrder::Rgba,
+        &mut out_buf,
+        &mut OpcodeState::new(),
+    )?;
+
+    Ok((out_buf, width, height))
+}
+
+/// Like `encode`, writing to an async writer, which is flushed at the end. Returns the total
+/// number of bytes written
+pub async fn encode_async<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    data: &[u8],
+    width: usize,
+    channels: ChannelCount,
+) -> Result<u64> {
+    let buf = encode_to_vec(data, width, channels)?;
+    writer.write_all(&buf).await?;
+    writer.flush().await?;
+
+    Ok(buf.len() as u64)
+}
@@ -0,0 +1,26 @@
+//! Browser bindings, exported with `wasm-bindgen`. Build with e.g.
+//! `wasm-pack build --target web --features wasm`
+use crate::{decode_converted, ChannelCount};
+use wasm_bindgen::prelude::*;
+
+/// A decoded image, whose `data` can be passed straight to `new ImageData(...)`
+#[wasm_bindgen(getter_with_clone)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA bytes, row by row
+    pub data: Vec<u8>,
+}
+
+/// Decodes a complete stream to RGBA. Errors are thrown as strings
+#[wasm_bindgen]
+pub fn decode_to_rgba(data: &[u8]) -> Result<DecodedImage, JsValue> {
+    let (data, width, height) = decode_converted(data, ChannelCount::Rgba)
+        .map_err(|err| JsValue::from_str(&format!("Failed to decode QOI image: {}", err)))?;
+
+    Ok(DecodedImage {
+        width,
+        height,
+        data,
+    })
+}
@@ -0,0 +1,34 @@
+//! The async adapters over tokio's I/O traits
+#![cfg(feature = "tokio")]
+mod common;
+
+use common::{Rng, ALL_CHANNELS};
+use qoi_rs::{decode_async, encode_async, encode_to_vec, ChannelCount, QoiError};
+
+#[tokio::test]
+async fn round_trip() {
+    let mut rng = Rng::new(0xA5C);
+    for channels in ALL_CHANNELS {
+        let data = rng.bytes(19 * 6 * channels.bytes_per_pixel());
+        let mut qoi = Vec::new();
+        let len = encode_async(&mut qoi, &data, 19, channels).await.unwrap();
+        assert_eq!(len as usize, qoi.len());
+        assert!(qoi == encode_to_vec(&data, 19, channels).unwrap());
+
+        let (decoded, width, height) = decode_async(&qoi[..], channels).await.unwrap();
+        assert_eq!((width, height), (19, 6));
+        assert!(decoded == data);
+    }
+}
+
+#[tokio::test]
+async fn rejects_truncated_stream() {
+    let qoi = encode_to_vec(&[7; 5 * 5 * 3], 5, ChannelCount::Rgb).unwrap();
+    // Within the header, then within the body
+    for len in [10, qoi.len() - 2] {
+        assert!(matches!(
+            decode_async(&qoi[..len], ChannelCount::Rgb).await,
+            Err(QoiError::UnexpectedEof)
+        ));
+    }
+}
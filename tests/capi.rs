@@ -0,0 +1,167 @@
+//! The C bindings, called the way C code would call them
+#![cfg(feature = "capi")]
+mod common;
+
+use common::{raw_stream, Rng};
+use qoi_rs::capi::{
+    qoi_decode, qoi_decoded_len, qoi_encode, qoi_free, QOI_ERR_DECODE, QOI_ERR_ENCODE,
+    QOI_ERR_INVALID_ARGUMENT, QOI_ERR_TOO_LARGE, QOI_OK,
+};
+use qoi_rs::{decode, ChannelCount};
+use std::ffi::c_int;
+use std::ptr;
+
+/// An error code which none of the functions store, to check that they overwrite it
+const UNSET: c_int = -1;
+
+/// Encodes through `qoi_encode`, returning the stream copied out of the library's buffer
+fn encode(data: &[u8], width: usize, channels: u8) -> Result<Vec<u8>, c_int> {
+    let mut len = usize::MAX;
+    let mut error = UNSET;
+    unsafe {
+        let buf = qoi_encode(
+            data.as_ptr(),
+            data.len(),
+            width,
+            channels,
+            &mut len,
+            &mut error,
+        );
+        if buf.is_null() {
+            assert_eq!(len, usize::MAX, "the length was written on failure");
+            return Err(error);
+        }
+        assert_eq!(error, QOI_OK);
+        let qoi = std::slice::from_raw_parts(buf, len).to_vec();
+        qoi_free(buf, len);
+        Ok(qoi)
+    }
+}
+
+/// Decodes through `qoi_decode`, returning (pixels, width, height)
+fn decode_c(qoi: &[u8], channels: u8) -> Result<(Vec<u8>, u32, u32), c_int> {
+    let (mut width, mut height) = (u32::MAX, u32::MAX);
+    let mut error = UNSET;
+    unsafe {
+        let buf = qoi_decode(
+            qoi.as_ptr(),
+            qoi.len(),
+            channels,
+            &mut width,
+            &mut height,
+            &mut error,
+        );
+        if buf.is_null() {
+            return Err(error);
+        }
+        assert_eq!(error, QOI_OK);
+        let len = qoi_decoded_len(width, height, channels);
+        let data = std::slice::from_raw_parts(buf, len).to_vec();
+        qoi_free(buf, len);
+        Ok((data, width, height))
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut rng = Rng::new(0xC0DE);
+    for channels in [1, 3, 4] {
+        let data = rng.bytes(13 * 5 * channels as usize);
+        let qoi = encode(&data, 13, channels).unwrap();
+
+        let (decoded, width, height) = decode_c(&qoi, channels).unwrap();
+        assert_eq!((width, height), (13, 5));
+        assert_eq!(decoded.len(), 13 * 5 * channels as usize);
+        assert!(decoded == data);
+
+        let expected = ChannelCount::try_from(channels).unwrap();
+        assert!(decode(&qoi[..], expected).unwrap().0 == data);
+    }
+}
+
+/// Decoding converts to the requested channel count, like `decode_converted`
+#[test]
+fn decode_converts() {
+    let qoi = encode(&[10, 20, 30].repeat(4), 2, 3).unwrap();
+    let (decoded, _, _) = decode_c(&qoi, 4).unwrap();
+    assert_eq!(decoded, [10, 20, 30, 255].repeat(4));
+}
+
+#[test]
+fn decoded_len() {
+    assert_eq!(qoi_decoded_len(7, 3, 1), 21);
+    assert_eq!(qoi_decoded_len(7, 3, 3), 63);
+    assert_eq!(qoi_decoded_len(7, 3, 4), 84);
+    assert_eq!(qoi_decoded_len(7, 3, 2), 0);
+}
+
+#[test]
+fn rejects_null_pointers() {
+    let data = [0u8; 4];
+    let qoi = encode(&data, 1, 4).unwrap();
+    let mut len = 0;
+    let (mut width, mut height) = (0, 0);
+    let mut error = UNSET;
+    unsafe {
+        let buf = qoi_encode(ptr::null(), 4, 1, 4, &mut len, &mut error);
+        assert!(buf.is_null());
+        assert_eq!(error, QOI_ERR_INVALID_ARGUMENT);
+
+        error = UNSET;
+        let buf = qoi_encode(data.as_ptr(), 4, 1, 4, ptr::null_mut(), &mut error);
+        assert!(buf.is_null());
+        assert_eq!(error, QOI_ERR_INVALID_ARGUMENT);
+
+        error = UNSET;
+        let buf = qoi_decode(ptr::null(), 0, 4, &mut width, &mut height, &mut error);
+        assert!(buf.is_null());
+        assert_eq!(error, QOI_ERR_INVALID_ARGUMENT);
+
+        error = UNSET;
+        let buf = qoi_decode(
+            qoi.as_ptr(),
+            qoi.len(),
+            4,
+            ptr::null_mut(),
+            &mut height,
+            &mut error,
+        );
+        assert!(buf.is_null());
+        assert_eq!(error, QOI_ERR_INVALID_ARGUMENT);
+
+        error = UNSET;
+        let buf = qoi_decode(
+            qoi.as_ptr(),
+            qoi.len(),
+            4,
+            &mut width,
+            ptr::null_mut(),
+            &mut error,
+        );
+        assert!(buf.is_null());
+        assert_eq!(error, QOI_ERR_INVALID_ARGUMENT);
+
+        // The error is optional, and so is a buffer to free
+        let buf = qoi_encode(data.as_ptr(), 4, 1, 4, &mut len, ptr::null_mut());
+        assert!(!buf.is_null());
+        qoi_free(buf, len);
+        qoi_free(ptr::null_mut(), 0);
+    }
+}
+
+#[test]
+fn reports_errors() {
+    // Data which doesn't fill whole rows, and a channel count which doesn't exist
+    assert_eq!(encode(&[0; 10], 3, 3), Err(QOI_ERR_ENCODE));
+    assert_eq!(encode(&[0; 4], 1, 2), Err(QOI_ERR_INVALID_ARGUMENT));
+
+    let qoi = encode(&[0; 4], 1, 4).unwrap();
+    assert_eq!(decode_c(&qoi[..10], 4), Err(QOI_ERR_DECODE));
+    assert_eq!(decode_c(b"not a qoi stream", 4), Err(QOI_ERR_DECODE));
+    assert_eq!(decode_c(&qoi, 0), Err(QOI_ERR_INVALID_ARGUMENT));
+
+    // 32768 x 8193 RGBA pixels is just over the 1 GiB limit of `decode`
+    let runs = [0x7F, 0xFF].repeat(32768 * 8193 / 0x2020 + 1);
+    let qoi = raw_stream(32768, 8193, ChannelCount::Rgba, 0, &runs);
+    assert_eq!(decode_c(&qoi, 4), Err(QOI_ERR_TOO_LARGE));
+}
@@ -0,0 +1,94 @@
+//! Helpers shared by the integration tests. Every image is generated from a fixed seed, so a
+//! failure always reproduces
+#![allow(dead_code)]
+
+use qoi_rs::{decode, decode_strict, encode_to_vec, ChannelCount, Rgba};
+
+pub const ALL_CHANNELS: [ChannelCount; 3] =
+    [ChannelCount::Gray, ChannelCount::Rgb, ChannelCount::Rgba];
+
+/// Size of the stream header
+pub const HEADER_SIZE: usize = 20;
+/// Byte offset of the compressed size field within the header
+pub const SIZE_FIELD_OFFSET: usize = 16;
+/// Zero bytes following the opcodes
+pub const PADDING: [u8; 4] = [0; 4];
+
+/// A small xorshift generator
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // Zero is the one state xorshift never leaves
+        Self(seed | 1)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// A number in `range`
+    pub fn range(&mut self, range: std::ops::RangeInclusive<usize>) -> usize {
+        range.start() + self.next_u32() as usize % (range.end() - range.start() + 1)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u32() as u8).collect()
+    }
+}
+
+/// `count` copies of `px`, keeping the first `channels` bytes of each
+pub fn repeat_pixel(px: Rgba, count: usize, channels: ChannelCount) -> Vec<u8> {
+    px[..channels.bytes_per_pixel()].repeat(count)
+}
+
+/// Encodes the image, checks that both `decode` and `decode_strict` give it back unchanged, and
+/// returns the stream
+pub fn round_trip(data: &[u8], width: usize, channels: ChannelCount) -> Vec<u8> {
+    let qoi = encode_to_vec(data, width, channels).expect("failed to encode");
+    let height = (data.len() / (width * channels.bytes_per_pixel())) as u32;
+
+    let (decoded, w, h) = decode(&qoi[..], channels).expect("failed to decode");
+    assert_eq!((w, h), (width as u32, height));
+    assert!(decoded == data, "decoded pixels differ from the input");
+
+    let (strict, _, _) = decode_strict(&qoi[..], channels).expect("not the canonical stream");
+    assert!(
+        strict == data,
+        "strictly decoded pixels differ from the input"
+    );
+
+    qoi
+}
+
+/// The opcodes of a stream, between the header and the padding
+pub fn opcodes(qoi: &[u8]) -> &[u8] {
+    assert!(qoi.ends_with(&PADDING), "the stream doesn't end in padding");
+    &qoi[HEADER_SIZE..qoi.len() - PADDING.len()]
+}
+
+/// Overwrites the compressed size in the header of `qoi`
+pub fn set_compressed_len(qoi: &mut [u8], len: u32) {
+    qoi[SIZE_FIELD_OFFSET..SIZE_FIELD_OFFSET + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// A stream with the given header fields and opcodes, followed by the padding
+pub fn raw_stream(
+    width: u32,
+    height: u32,
+    channels: ChannelCount,
+    flags: u8,
+    opcodes: &[u8],
+) -> Vec<u8> {
+    let mut qoi = b"qoiv\x04".to_vec();
+    qoi.extend_from_slice(&width.to_le_bytes());
+    qoi.extend_from_slice(&height.to_le_bytes());
+    qoi.extend_from_slice(&[channels as u8, 0, flags]);
+    qoi.extend_from_slice(&((opcodes.len() + PADDING.len()) as u32).to_le_bytes());
+    qoi.extend_from_slice(opcodes);
+    qoi.extend_from_slice(&PADDING);
+    qoi
+}
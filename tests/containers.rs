@@ -0,0 +1,199 @@
+//! The multi-band and animation containers around QOI streams, and the metadata trailer after them
+mod common;
+
+use common::Rng;
+use qoi_rs::{
+    decode, encode_to_vec, encode_with_metadata, read_metadata, AnimDecoder, AnimEncoder,
+    ChannelCount, QoiError,
+};
+use std::io::Cursor;
+
+/// Byte offset of the version within both container headers
+const VERSION_OFFSET: usize = 4;
+
+fn animation(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    let mut encoder = AnimEncoder::new(&mut out, 5, 3, ChannelCount::Rgba, 40).unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        if i % 2 == 0 {
+            encoder.add_frame(frame).unwrap();
+        } else {
+            encoder.add_delta_frame(frame).unwrap();
+        }
+    }
+    encoder.finish().unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn animation_round_trip() {
+    let mut rng = Rng::new(0xA171);
+    let frames: Vec<Vec<u8>> = (0..5).map(|_| rng.bytes(5 * 3 * 4)).collect();
+    let anim = animation(&frames);
+
+    let decoder = AnimDecoder::new(&anim[..]).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (5, 3));
+    assert_eq!((decoder.frame_count(), decoder.delay_ms()), (5, 40));
+    let decoded: Vec<Vec<u8>> = decoder.map(|frame| frame.unwrap().data).collect();
+    assert_eq!(decoded, frames);
+}
+
+#[test]
+fn animation_version_is_its_own() {
+    let mut anim = animation(&[vec![0; 5 * 3 * 4]]);
+    assert_eq!(anim[VERSION_OFFSET], 1);
+    anim[VERSION_OFFSET] = 2;
+    assert!(matches!(
+        AnimDecoder::new(&anim[..]),
+        Err(QoiError::UnsupportedVersion(2))
+    ));
+}
+
+fn with_metadata(data: &[u8], metadata: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    let len = encode_with_metadata(&mut out, data, 4, ChannelCount::Rgb, metadata).unwrap();
+    let out = out.into_inner();
+    assert_eq!(len as usize, out.len());
+    out
+}
+
+#[test]
+fn metadata_round_trip() {
+    let data = Rng::new(0x3E7A).bytes(4 * 3 * 3);
+    let metadata = vec![
+        ("source".to_string(), b"render.exr".to_vec()),
+        ("empty".to_string(), Vec::new()),
+        (String::new(), vec![0, 1, 2, 255]),
+    ];
+    let qoi = with_metadata(&data, &metadata);
+    assert_eq!(read_metadata(&qoi[..]).unwrap(), metadata);
+
+    // The stream comes first, unchanged, and decodes as usual
+    let stream = encode_to_vec(&data, 4, ChannelCount::Rgb).unwrap();
+    assert!(qoi.starts_with(&stream));
+    assert!(decode(&qoi[..], ChannelCount::Rgb).unwrap().0 == data);
+
+    assert!(read_metadata(&with_metadata(&data, &[])[..])
+        .unwrap()
+        .is_empty());
+}
+
+/// A stream on its own, or followed by anything other than a trailer, has no metadata
+#[test]
+fn metadata_without_trailer() {
+    let stream = encode_to_vec(&[5; 4 * 3 * 3], 4, ChannelCount::Rgb).unwrap();
+    assert!(read_metadata(&stream[..]).unwrap().is_empty());
+    let followed = [&stream[..], b"not metadata"].concat();
+    assert!(read_metadata(&followed[..]).unwrap().is_empty());
+
+    // The stream itself must be whole
+    assert!(matches!(
+        read_metadata(&stream[..stream.len() - 1]),
+        Err(QoiError::UnexpectedEof)
+    ));
+}
+
+#[test]
+fn metadata_rejects_bad_trailer() {
+    let metadata = [("key".to_string(), b"value".to_vec())];
+    let qoi = with_metadata(&[5; 4 * 3 * 3], &metadata);
+    // Magic, entries size, then the key and value lengths each before their field
+    let entries_at = qoi.len() - (4 + 3 + 4 + 5);
+    let entries_len_at = entries_at - 4;
+
+    // Cut off within the entries
+    assert!(matches!(
+        read_metadata(&qoi[..qoi.len() - 1]),
+        Err(QoiError::UnexpectedEof)
+    ));
+
+    // A value length past the end of the entries
+    let mut long_value = qoi.clone();
+    long_value[entries_at + 4 + 3] = 6;
+    assert!(matches!(
+        read_metadata(&long_value[..]),
+        Err(QoiError::InvalidMetadata)
+    ));
+
+    // Entries which end partway through a length
+    let mut short_entries = qoi.clone();
+    short_entries[entries_len_at] = 2;
+    assert!(matches!(
+        read_metadata(&short_entries[..entries_at + 2]),
+        Err(QoiError::InvalidMetadata)
+    ));
+
+    // A key which isn't UTF-8
+    let mut bad_key = qoi;
+    bad_key[entries_at + 4] = 0xFF;
+    assert!(matches!(
+        read_metadata(&bad_key[..]),
+        Err(QoiError::InvalidMetadata)
+    ));
+}
+
+#[cfg(feature = "rayon")]
+mod parallel {
+    use super::*;
+    use qoi_rs::{decode_parallel, encode_parallel, is_parallel_container};
+
+    /// Byte offset of the channel count within the container header
+    const CHANNELS_OFFSET: usize = 13;
+    /// Tall enough to need three bands
+    const WIDTH: usize = 9;
+    const HEIGHT: usize = 150;
+
+    fn container(data: &[u8], channels: ChannelCount) -> Vec<u8> {
+        let mut out = Vec::new();
+        let len = encode_parallel(&mut out, data, WIDTH, channels).unwrap();
+        assert_eq!(len, out.len());
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng::new(0xBA4D);
+        for channels in common::ALL_CHANNELS {
+            let data = rng.bytes(WIDTH * HEIGHT * channels.bytes_per_pixel());
+            let qoi = container(&data, channels);
+            assert!(is_parallel_container(&qoi));
+            let (decoded, width, height) = decode_parallel(&qoi, channels).unwrap();
+            assert_eq!((width, height), (WIDTH as u32, HEIGHT as u32));
+            assert!(decoded == data);
+        }
+    }
+
+    #[test]
+    fn rejects_other_channel_count() {
+        let qoi = container(&[0; WIDTH * HEIGHT * 3], ChannelCount::Rgb);
+        assert!(matches!(
+            decode_parallel(&qoi, ChannelCount::Rgba),
+            Err(QoiError::ChannelMismatch {
+                expected: ChannelCount::Rgb,
+                requested: ChannelCount::Rgba
+            })
+        ));
+
+        // The container's own channel count is checked, not just the bands'
+        let mut qoi = qoi;
+        qoi[CHANNELS_OFFSET] = 4;
+        assert!(matches!(
+            decode_parallel(&qoi, ChannelCount::Rgb),
+            Err(QoiError::ChannelMismatch {
+                expected: ChannelCount::Rgba,
+                requested: ChannelCount::Rgb
+            })
+        ));
+    }
+
+    #[test]
+    fn version_is_its_own() {
+        let mut qoi = container(&[0; WIDTH * HEIGHT], ChannelCount::Gray);
+        assert_eq!(qoi[VERSION_OFFSET], 1);
+        qoi[VERSION_OFFSET] = 2;
+        assert!(matches!(
+            decode_parallel(&qoi, ChannelCount::Gray),
+            Err(QoiError::UnsupportedVersion(2))
+        ));
+    }
+}
@@ -0,0 +1,561 @@
+//! Every error is reported as its own variant, with a readable message
+mod common;
+
+use common::{raw_stream, repeat_pixel, set_compressed_len, Rng, ALL_CHANNELS, HEADER_SIZE};
+use qoi_rs::{
+    decode, decode_partial, decode_pixels, decode_region, decode_strict, decode_with_limit, encode,
+    encode_dims, encode_from_iter, encode_into_buffer, encode_strided, encode_to_vec,
+    encode_with_options, encode_with_stats, min_encoded_size, verify, ChannelCount, Decoder,
+    DecoderState, EncodeOptions, LineEncoder, QoiError, QoiImage, MAX_DIMENSION,
+};
+use std::error::Error;
+use std::io::{self, Cursor, Read};
+
+/// Checks that `err` formats as a non-empty message, with a source only for I/O errors
+fn check_display(err: &QoiError) {
+    assert!(!err.to_string().is_empty());
+    assert_eq!(err.source().is_some(), matches!(err, QoiError::Io(_)));
+}
+
+fn encode_err(data: &[u8], width: usize, channels: ChannelCount) -> QoiError {
+    let err = encode_to_vec(data, width, channels).unwrap_err();
+    check_display(&err);
+    err
+}
+
+fn decode_err(qoi: &[u8], channels: ChannelCount) -> QoiError {
+    let err = decode(qoi, channels).unwrap_err();
+    check_display(&err);
+    err
+}
+
+#[test]
+fn encode_rejects_mismatched_data() {
+    let err = encode_err(&[0; 7], 1, ChannelCount::Rgb);
+    assert!(matches!(err, QoiError::DataNotMultipleOfChannels));
+    let err = encode_err(&[0; 12], 3, ChannelCount::Rgb);
+    assert!(matches!(err, QoiError::WidthDoesNotDivideData));
+
+    // The width counts pixels, not bytes: 12 bytes are 3 RGBA pixels, which don't fill rows of 2
+    // even though 12 is a multiple of 2
+    let err = encode_err(&[0; 12], 2, ChannelCount::Rgba);
+    assert!(matches!(err, QoiError::WidthDoesNotDivideData));
+    let err = encode_err(&[0; 4 * 6], 4, ChannelCount::Rgba);
+    assert!(matches!(err, QoiError::WidthDoesNotDivideData));
+}
+
+/// A width of 0 is an error rather than a division by zero, whatever the encoder
+#[test]
+fn encode_rejects_zero_width() {
+    for channels in ALL_CHANNELS {
+        let data = repeat_pixel([1, 2, 3, 4], 6, channels);
+        assert!(matches!(
+            encode_err(&data, 0, channels),
+            QoiError::ZeroDimension
+        ));
+        let results = [
+            encode(Cursor::new(Vec::new()), &data, 0, channels).map(drop),
+            encode_with_stats(io::sink(), &data, 0, channels).map(drop),
+            encode_into_buffer(&data, 0, channels, &mut [0; 64]).map(drop),
+            LineEncoder::new(io::sink(), 0, 6, channels).map(drop),
+        ];
+        for result in results {
+            assert!(matches!(result, Err(QoiError::ZeroDimension)));
+        }
+    }
+}
+
+#[test]
+fn encode_rejects_oversized_dimensions() {
+    let err = LineEncoder::new(
+        io::sink(),
+        MAX_DIMENSION as usize + 1,
+        1,
+        ChannelCount::Gray,
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(err, QoiError::DimensionTooLarge));
+}
+
+#[test]
+fn encode_rejects_short_rows() {
+    let data = [0; 4 * 3 * 2];
+    let err = encode_strided(Cursor::new(Vec::new()), &data, 4, 2, 8, ChannelCount::Rgb);
+    assert!(matches!(
+        err,
+        Err(QoiError::StrideTooSmall {
+            stride: 8,
+            row_len: 12
+        })
+    ));
+
+    let err = encode_strided(Cursor::new(Vec::new()), &data, 4, 3, 12, ChannelCount::Rgb);
+    assert!(matches!(
+        err,
+        Err(QoiError::DataTooShort {
+            required: 36,
+            available: 24
+        })
+    ));
+    // Sizes whose products overflow are errors rather than panics
+    let err = encode_strided(
+        Cursor::new(Vec::new()),
+        &data,
+        4,
+        usize::MAX,
+        12,
+        ChannelCount::Rgb,
+    );
+    assert!(matches!(err, Err(QoiError::DimensionTooLarge)));
+    let err = encode_strided(
+        Cursor::new(Vec::new()),
+        &data,
+        4,
+        3,
+        usize::MAX,
+        ChannelCount::Rgb,
+    );
+    assert!(matches!(
+        err,
+        Err(QoiError::DataTooShort {
+            required: usize::MAX,
+            available: 24
+        })
+    ));
+    let err = encode_dims(
+        Cursor::new(Vec::new()),
+        &data,
+        usize::MAX,
+        1,
+        ChannelCount::Rgb,
+    );
+    assert!(matches!(err, Err(QoiError::DimensionTooLarge)));
+}
+
+#[test]
+fn encode_into_buffer_rejects_small_buffer() {
+    let data = [7; 3 * 3];
+    let err = encode_into_buffer(&data, 3, ChannelCount::Rgb, &mut [0; 10]).unwrap_err();
+    check_display(&err);
+    assert!(matches!(
+        err,
+        QoiError::BufferTooSmall { available: 10, .. }
+    ));
+}
+
+#[test]
+fn line_encoder_counts_rows() {
+    let row = [0; 2 * 3];
+    let mut encoder = LineEncoder::new(io::sink(), 2, 2, ChannelCount::Rgb).unwrap();
+    encoder.write_line(&row).unwrap();
+    let err = encoder.finish().unwrap_err();
+    assert!(matches!(
+        err,
+        QoiError::RowCountMismatch {
+            expected: 2,
+            written: 1
+        }
+    ));
+
+    let mut encoder = LineEncoder::new(io::sink(), 2, 1, ChannelCount::Rgb).unwrap();
+    encoder.write_line(&row).unwrap();
+    let err = encoder.write_line(&row).unwrap_err();
+    assert!(matches!(
+        err,
+        QoiError::RowCountMismatch {
+            expected: 1,
+            written: 2
+        }
+    ));
+}
+
+#[test]
+fn no_two_channel_layout() {
+    assert!(matches!(
+        ChannelCount::try_from(2),
+        Err(QoiError::BadChannelCount(2))
+    ));
+}
+
+#[test]
+fn decode_rejects_other_channel_count() {
+    for stored in ALL_CHANNELS {
+        let qoi = encode_to_vec(&repeat_pixel([1, 2, 3, 4], 4, stored), 2, stored).unwrap();
+        for requested in ALL_CHANNELS.into_iter().filter(|&c| c != stored) {
+            let err = decode_err(&qoi, requested);
+            assert!(matches!(
+                err,
+                QoiError::ChannelMismatch { expected, requested: r } if expected == stored && r == requested
+            ));
+        }
+    }
+}
+
+#[test]
+fn decode_rejects_over_limit() {
+    let qoi = encode_to_vec(&[0; 16 * 16 * 4], 16, ChannelCount::Rgba).unwrap();
+    let err = decode_with_limit(&qoi[..], ChannelCount::Rgba, 1023).unwrap_err();
+    assert!(matches!(
+        err,
+        QoiError::TooLarge {
+            required: 1024,
+            limit: 1023
+        }
+    ));
+}
+
+/// `verify` refuses what `decode` refuses to allocate, though it allocates nothing itself
+#[test]
+fn verify_rejects_over_limit() {
+    // 32768 x 8193 RGBA pixels is just over 1 GiB, and at least this many runs long
+    let runs = [0x7F, 0xFF].repeat(32768 * 8193 / 0x2020 + 1);
+    let qoi = raw_stream(32768, 8193, ChannelCount::Rgba, 0, &runs);
+    for err in [
+        decode_err(&qoi, ChannelCount::Rgba),
+        verify(&qoi[..]).unwrap_err(),
+    ] {
+        assert!(matches!(
+            err,
+            QoiError::TooLarge {
+                required: 0x4002_0000,
+                limit: 0x4000_0000
+            }
+        ));
+    }
+}
+
+#[test]
+fn decode_rejects_truncated_body() {
+    let qoi = encode_to_vec(&[1, 2, 3, 4, 5, 6], 2, ChannelCount::Rgb).unwrap();
+    for len in 20..qoi.len() {
+        let err = decode_err(&qoi[..len], ChannelCount::Rgb);
+        assert!(matches!(err, QoiError::UnexpectedEof), "{:?}", err);
+    }
+}
+
+#[test]
+fn decode_rejects_bad_padding() {
+    let mut qoi = encode_to_vec(&[1, 2, 3], 1, ChannelCount::Rgb).unwrap();
+    *qoi.last_mut().unwrap() = 1;
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgb),
+        QoiError::BadPadding
+    ));
+}
+
+#[test]
+fn decode_rejects_trailing_data() {
+    // An extra opcode where the padding should start
+    let qoi = raw_stream(1, 1, ChannelCount::Rgb, 0, &[0x40, 0x40]);
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgb),
+        QoiError::BadPadding
+    ));
+
+    // An extra byte after the padding, counted in the declared length
+    let mut qoi = raw_stream(1, 1, ChannelCount::Rgb, 0, &[0x40]);
+    qoi.push(0);
+    set_compressed_len(&mut qoi, 6);
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgb),
+        QoiError::SizeMismatch {
+            declared: 6,
+            actual: 5
+        }
+    ));
+}
+
+#[test]
+fn decode_rejects_corrupt_crc() {
+    let data = [9; 4 * 4 * 3];
+    let mut qoi = Cursor::new(Vec::new());
+    let options = EncodeOptions::new().crc(true);
+    encode_with_options(&mut qoi, &data, 4, ChannelCount::Rgb, &options).unwrap();
+    let mut qoi = qoi.into_inner();
+    *qoi.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgb),
+        QoiError::ChecksumMismatch { .. }
+    ));
+}
+
+/// A changed pixel still decodes to a whole image, so only the checksum catches it
+#[test]
+fn decode_rejects_corrupt_body() {
+    let data = repeat_pixel([200, 100, 50, 255], 4 * 4, ChannelCount::Rgb);
+    let mut qoi = Cursor::new(Vec::new());
+    let options = EncodeOptions::new().crc(true);
+    encode_with_options(&mut qoi, &data, 4, ChannelCount::Rgb, &options).unwrap();
+    let mut qoi = qoi.into_inner();
+    // The red of the QOI_COLOR which opens the body
+    assert_eq!(qoi[HEADER_SIZE..HEADER_SIZE + 4], [0xFE, 200, 100, 50]);
+    qoi[HEADER_SIZE + 1] ^= 1;
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgb),
+        QoiError::ChecksumMismatch { .. }
+    ));
+}
+
+#[test]
+fn decode_rejects_run_across_rows() {
+    // A run of 3 in a 2x2 image which resets the previous pixel at every row
+    let qoi = raw_stream(2, 2, ChannelCount::Rgba, 0b10, &[0x42, 0x40]);
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgba),
+        QoiError::RunAcrossRows
+    ));
+}
+
+#[test]
+fn decode_rejects_run_past_end() {
+    // A run of 6 in a 2x2 image, otherwise well formed
+    let qoi = raw_stream(2, 2, ChannelCount::Rgba, 0, &[0x45]);
+    assert!(matches!(
+        decode_err(&qoi, ChannelCount::Rgba),
+        QoiError::RunPastEnd
+    ));
+    assert!(matches!(verify(&qoi[..]), Err(QoiError::RunPastEnd)));
+    assert!(matches!(
+        decode_strict(&qoi[..], ChannelCount::Rgba),
+        Err(QoiError::RunPastEnd)
+    ));
+    assert!(matches!(
+        decode_region(&qoi[..], ChannelCount::Rgba, 0, 0, 1, 1),
+        Err(QoiError::RunPastEnd)
+    ));
+
+    // The run of exactly 4 decodes
+    let qoi = raw_stream(2, 2, ChannelCount::Rgba, 0, &[0x43]);
+    assert!(decode(&qoi[..], ChannelCount::Rgba).is_ok());
+}
+
+#[test]
+fn strict_decode_rejects_other_encoders() {
+    // Slot 5 of the index has never been written
+    let qoi = raw_stream(1, 1, ChannelCount::Rgba, 0, &[0x05]);
+    let err = decode_strict(&qoi[..], ChannelCount::Rgba).unwrap_err();
+    check_display(&err);
+    assert!(matches!(err, QoiError::UnwrittenIndex(5)));
+
+    // A QOI_COLOR which changes nothing, where this encoder writes a run
+    let qoi = raw_stream(1, 1, ChannelCount::Rgba, 0, &[0xF0]);
+    assert!(decode(&qoi[..], ChannelCount::Rgba).is_ok());
+    let err = decode_strict(&qoi[..], ChannelCount::Rgba).unwrap_err();
+    assert!(matches!(err, QoiError::NonCanonical { .. }));
+}
+
+#[test]
+fn region_must_fit() {
+    let qoi = encode_to_vec(&[0; 4 * 4], 4, ChannelCount::Gray).unwrap();
+    let err = decode_region(&qoi[..], ChannelCount::Gray, 2, 2, 3, 1).unwrap_err();
+    check_display(&err);
+    assert!(matches!(err, QoiError::RegionOutOfBounds));
+    let err = decode_region(&qoi[..], ChannelCount::Gray, 0, 0, 0, 1).unwrap_err();
+    assert!(matches!(err, QoiError::ZeroDimension));
+}
+
+#[test]
+fn io_errors_keep_their_source() {
+    struct Failing;
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("disconnected"))
+        }
+    }
+
+    let err = decode(Failing, ChannelCount::Rgb).unwrap_err();
+    check_display(&err);
+    assert_eq!(err.source().unwrap().to_string(), "disconnected");
+}
+
+/// The size field is checked against the opcodes actually read, whether it claims more or less
+#[test]
+fn decode_rejects_corrupt_compressed_len() {
+    let data: Vec<u8> = (0..8 * 8 * 3).map(|i| (i * 7) as u8).collect();
+    let qoi = encode_to_vec(&data, 8, ChannelCount::Rgb).unwrap();
+    let len = (qoi.len() - 20) as u32;
+
+    let corrupt = |declared: u32| {
+        let mut corrupt = qoi.clone();
+        corrupt[16..20].copy_from_slice(&declared.to_le_bytes());
+        decode_err(&corrupt, ChannelCount::Rgb)
+    };
+
+    // Longer than the stream: the opcodes and padding end early
+    for declared in [len + 1, len + 100, u32::MAX] {
+        let err = corrupt(declared);
+        assert!(
+            matches!(err, QoiError::SizeMismatch { declared: d, actual } if d == declared && actual == len as usize),
+            "{:?}",
+            err
+        );
+    }
+    // Shorter than the stream: the last opcodes or the padding lie past the declared end
+    for declared in [len - 1, len - 4, len / 2] {
+        let err = corrupt(declared);
+        assert!(matches!(err, QoiError::UnexpectedEof), "{:?}", err);
+    }
+}
+
+/// QOI has no 0x0 image: encoding one fails cleanly, and so does decoding a header claiming one
+#[test]
+fn empty_image() {
+    for channels in ALL_CHANNELS {
+        let err = encode_err(&[], 0, channels);
+        assert!(matches!(err, QoiError::ZeroDimension));
+        let err = encode_err(&[], 1, channels);
+        assert!(matches!(err, QoiError::EmptyImage));
+
+        let empty = std::iter::empty();
+        let err = encode_from_iter(Cursor::new(Vec::new()), empty, 0, 0, channels).unwrap_err();
+        assert!(matches!(err, QoiError::ZeroDimension));
+
+        for (width, height) in [(0, 0), (0, 3), (3, 0)] {
+            let qoi = raw_stream(width, height, channels, 0, &[]);
+            let err = decode_err(&qoi, channels);
+            assert!(matches!(err, QoiError::ZeroDimension), "{:?}", err);
+            assert!(matches!(verify(&qoi[..]), Err(QoiError::ZeroDimension)));
+            assert!(matches!(
+                decode_pixels(&qoi[..]),
+                Err(QoiError::ZeroDimension)
+            ));
+        }
+    }
+}
+
+/// The push decoder tells an invalid stream apart from one which has only arrived in part
+#[test]
+fn decoder_reports_invalid_streams() {
+    let qoi = raw_stream(2, 2, ChannelCount::Rgba, 0b10, &[0x42, 0x40]);
+    let mut decoder = Decoder::new();
+    decoder.feed(&qoi[..20]).unwrap();
+    assert_eq!(decoder.pixels().count(), 0);
+    assert!(decoder.error().is_none());
+
+    // The run fills the first row, then fails at the second
+    decoder.feed(&qoi[20..]).unwrap();
+    assert_eq!(decoder.pixels().count(), 2);
+    let err = decoder.error().unwrap();
+    check_display(err);
+    assert!(matches!(err, QoiError::RunAcrossRows));
+
+    // Decoding stops for good, however many bytes follow
+    decoder.feed(&[0; 16]).unwrap();
+    assert_eq!(decoder.pixels().count(), 0);
+    assert!(!decoder.is_finished());
+}
+
+/// A failed decode leaves no part of either image behind
+#[test]
+fn decoder_state_forgets_failed_image() {
+    let first = repeat_pixel([10, 20, 30, 255], 4 * 4, ChannelCount::Rgb);
+    let second = Rng::new(0x57A7).bytes(4 * 4 * 3);
+    let first_qoi = encode_to_vec(&first, 4, ChannelCount::Rgb).unwrap();
+    let second_qoi = encode_to_vec(&second, 4, ChannelCount::Rgb).unwrap();
+
+    let mut state = DecoderState::new();
+    assert_eq!(
+        state
+            .decode_into(&first_qoi[..], ChannelCount::Rgb)
+            .unwrap(),
+        (4, 4)
+    );
+    assert!(state.data() == first);
+
+    // Half of the body decodes before the stream runs out
+    let truncated = &second_qoi[..HEADER_SIZE + (second_qoi.len() - HEADER_SIZE) / 2];
+    assert!(state.decode_into(truncated, ChannelCount::Rgb).is_err());
+    assert!(state.data().is_empty());
+
+    state
+        .decode_into(&second_qoi[..], ChannelCount::Rgb)
+        .unwrap();
+    assert!(state.data() == second);
+}
+
+/// The pixels before the stream runs out are kept, along with the reason it stopped
+#[test]
+fn partial_decode_keeps_prefix() {
+    #[rustfmt::skip]
+    let qoi = raw_stream(2, 2, ChannelCount::Rgb, 0, &[
+        0xFE, 1, 2, 3,
+        0xFE, 40, 50, 60,
+        0xFE, 70, 80, 90,
+        0xFE, 100, 110, 120,
+    ]);
+    // Cut off within the third pixel
+    let truncated = &qoi[..HEADER_SIZE + 9];
+
+    let partial = decode_partial(truncated, ChannelCount::Rgb).unwrap();
+    assert_eq!((partial.width, partial.height), (2, 2));
+    assert_eq!(partial.data.len(), 2 * 2 * 3);
+    assert_eq!(partial.pixels_decoded, 2);
+    assert_eq!(partial.data[..6], [1, 2, 3, 40, 50, 60]);
+    assert!(matches!(partial.error, Some(QoiError::UnexpectedEof)));
+
+    let whole = decode_partial(&qoi[..], ChannelCount::Rgb).unwrap();
+    assert_eq!(whole.pixels_decoded, 4);
+    assert!(whole.error.is_none());
+
+    // Errors in the header are still errors of the call
+    assert!(matches!(
+        decode_partial(truncated, ChannelCount::Rgba),
+        Err(QoiError::ChannelMismatch { .. })
+    ));
+    assert!(matches!(
+        decode_partial(&qoi[..10], ChannelCount::Rgb),
+        Err(QoiError::UnexpectedEof)
+    ));
+}
+
+/// The pixel decoders don't allocate the image, so they take dimensions whose pixel count
+/// overflows a 32-bit usize, and stop where the data does
+#[test]
+fn pixel_decoders_take_huge_dimensions() {
+    let (width, height) = (MAX_DIMENSION, 1 << 9);
+    let mut qoi = raw_stream(width, height, ChannelCount::Rgba, 0, &[0x40]);
+    // The fewest bytes which could hold that many pixels, of which only the first run arrives
+    set_compressed_len(
+        &mut qoi,
+        (min_encoded_size(width, height) - HEADER_SIZE) as u32,
+    );
+    let qoi = &qoi[..HEADER_SIZE + 1];
+
+    let mut pixels = decode_pixels(qoi).unwrap();
+    assert_eq!(pixels.next().unwrap().unwrap(), [0, 0, 0, 255]);
+    assert!(matches!(pixels.next(), Some(Err(QoiError::UnexpectedEof))));
+    assert!(pixels.next().is_none());
+
+    let mut decoder = Decoder::new();
+    decoder.feed(qoi).unwrap();
+    assert_eq!(decoder.pixels().count(), 1);
+    assert!(decoder.error().is_none());
+    assert!(!decoder.is_finished());
+}
+
+/// `QoiImage` checks its data the way the encoders do
+#[test]
+fn image_rejects_bad_data() {
+    let new = |len: usize, width: usize| QoiImage::new(vec![0; len], width, ChannelCount::Rgb);
+    assert!(matches!(new(12, 0), Err(QoiError::ZeroDimension)));
+    assert!(matches!(new(0, 2), Err(QoiError::EmptyImage)));
+    assert!(matches!(
+        new(10, 2),
+        Err(QoiError::DataNotMultipleOfChannels)
+    ));
+    assert!(matches!(new(9, 2), Err(QoiError::WidthDoesNotDivideData)));
+    assert!(matches!(
+        QoiImage::new(
+            vec![0; MAX_DIMENSION as usize + 1],
+            MAX_DIMENSION as usize + 1,
+            ChannelCount::Gray
+        ),
+        Err(QoiError::DimensionTooLarge)
+    ));
+
+    assert!(matches!(
+        QoiImage::try_from(&b"\x89PNG and not a stream"[..]),
+        Err(QoiError::BadMagic(magic)) if &magic == b"\x89PNG"
+    ));
+}
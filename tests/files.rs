@@ -0,0 +1,46 @@
+//! Writing and reading `.qoi` files on disk
+mod common;
+
+use common::{Rng, HEADER_SIZE, SIZE_FIELD_OFFSET};
+use qoi_rs::{encode_to_vec, read_from_file, write_to_file, ChannelCount};
+use std::fs;
+use std::path::PathBuf;
+
+/// A path in the temporary directory which no other test process uses, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let file = format!("qoi-rs-{}-{}.qoi", std::process::id(), name);
+        Self(std::env::temp_dir().join(file))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The size is written back into the header after the body, through the `BufWriter`, so images
+/// both smaller and larger than its buffer must end up with the right size field
+#[test]
+fn write_to_file_sets_size() {
+    let mut rng = Rng::new(0xF11E);
+    for (width, height) in [(1, 1), (300, 300), (700, 500)] {
+        let data = rng.bytes(width * height * 4);
+        let file = TempFile::new(&format!("{}x{}", width, height));
+        write_to_file(&file.0, &data, width, ChannelCount::Rgba).unwrap();
+
+        let qoi = fs::read(&file.0).unwrap();
+        let size_field = &qoi[SIZE_FIELD_OFFSET..SIZE_FIELD_OFFSET + 4];
+        let size = u32::from_le_bytes(size_field.try_into().unwrap()) as usize;
+        assert_ne!(size, 0);
+        assert_eq!(size, qoi.len() - HEADER_SIZE);
+        assert!(qoi == encode_to_vec(&data, width, ChannelCount::Rgba).unwrap());
+
+        let (decoded, w, h) = read_from_file(&file.0, ChannelCount::Rgba).unwrap();
+        assert_eq!((w, h), (width as u32, height as u32));
+        assert!(decoded == data);
+    }
+}
@@ -0,0 +1,211 @@
+//! Checks the golden files in `fixtures/`, which were written by this crate's encoder. Every
+//! `<name>.qoi` must decode to the pixels in `<name>.raw`, and encoding those pixels with the
+//! fixture's options must reproduce the `.qoi` byte for byte.
+//!
+//! A header written out byte by byte is parsed too, pinning the little-endian fields whatever the
+//! host's byte order.
+//!
+//! `cargo test --test fixtures` checks them. With `QOI_WRITE_FIXTURES=1` set, it first writes any
+//! fixture which doesn't exist yet. Existing fixtures are never rewritten: they stand for files
+//! already out in the world
+use qoi_rs::{
+    decode_auto, encode_with_options, read_header, ChannelCount, Colorspace, EncodeOptions,
+    QoiHeader,
+};
+use std::io::Cursor;
+use std::path::Path;
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 24;
+
+struct Fixture {
+    name: &'static str,
+    channels: ChannelCount,
+    options: EncodeOptions,
+    pixel: fn(usize, usize) -> [u8; 4],
+}
+
+fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture {
+            name: "gradient_rgba",
+            channels: ChannelCount::Rgba,
+            options: EncodeOptions::new(),
+            pixel: gradient,
+        },
+        Fixture {
+            name: "noise_rgba",
+            channels: ChannelCount::Rgba,
+            options: EncodeOptions::new(),
+            pixel: noise,
+        },
+        Fixture {
+            name: "palette_rgb",
+            channels: ChannelCount::Rgb,
+            options: EncodeOptions::new(),
+            pixel: palette,
+        },
+        Fixture {
+            name: "solid_gray",
+            channels: ChannelCount::Gray,
+            options: EncodeOptions::new(),
+            pixel: solid,
+        },
+        Fixture {
+            name: "crc_rgb",
+            channels: ChannelCount::Rgb,
+            options: EncodeOptions::new().crc(true),
+            pixel: gradient,
+        },
+        Fixture {
+            name: "linear_rgba",
+            channels: ChannelCount::Rgba,
+            options: EncodeOptions::new().colorspace(Colorspace::Linear),
+            pixel: palette,
+        },
+        Fixture {
+            name: "no_index_rgba",
+            channels: ChannelCount::Rgba,
+            options: EncodeOptions::new().use_index(false),
+            pixel: palette,
+        },
+        Fixture {
+            name: "reset_rows_rgba",
+            channels: ChannelCount::Rgba,
+            options: EncodeOptions::new().reset_rows(true),
+            pixel: gradient,
+        },
+    ]
+}
+
+/// Small steps in every channel, encoded as diffs of all three sizes
+fn gradient(x: usize, y: usize) -> [u8; 4] {
+    [
+        (x + x / 4 * 8 + y) as u8,
+        (x / 2 + y) as u8,
+        (y * 3) as u8,
+        255 - (x / 8 * 3) as u8,
+    ]
+}
+
+/// Unrelated neighbours, encoded as whole colors
+fn noise(x: usize, y: usize) -> [u8; 4] {
+    let h = ((x * 7919 + y * 104_729) as u32).wrapping_mul(0x9E37_79B9);
+    h.to_le_bytes()
+}
+
+/// A few colors repeating in small blocks, encoded as index lookups and short runs
+fn palette(x: usize, y: usize) -> [u8; 4] {
+    const COLORS: [[u8; 4]; 4] = [
+        [20, 12, 28, 255],
+        [208, 70, 72, 255],
+        [52, 101, 36, 255],
+        [222, 238, 214, 128],
+    ];
+    COLORS[(x / 3 + y / 2) % 4]
+}
+
+/// One color, encoded as a single long run
+fn solid(_: usize, _: usize) -> [u8; 4] {
+    [90, 90, 90, 255]
+}
+
+/// Set to write the fixtures which don't exist yet
+const WRITE_VAR: &str = "QOI_WRITE_FIXTURES";
+
+#[test]
+fn fixtures_still_match() {
+    let write = std::env::var_os(WRITE_VAR).is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+
+    let mut failures = Vec::new();
+
+    for Fixture {
+        name,
+        channels,
+        options,
+        pixel,
+    } in fixtures()
+    {
+        let qoi_path = dir.join(name).with_extension("qoi");
+        let raw_path = dir.join(name).with_extension("raw");
+
+        if write && !qoi_path.exists() {
+            let raw: Vec<u8> = (0..HEIGHT)
+                .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+                .flat_map(|(x, y)| pixel(x, y)[..channels.bytes_per_pixel()].to_vec())
+                .collect();
+            let mut qoi = Cursor::new(Vec::new());
+            encode_with_options(&mut qoi, &raw, WIDTH, channels, &options).unwrap();
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(&qoi_path, qoi.into_inner()).unwrap();
+            std::fs::write(&raw_path, raw).unwrap();
+            eprintln!("{}: written", name);
+        }
+
+        if let Err(err) = check(&qoi_path, &raw_path, channels, &options) {
+            failures.push(format!("{}: {}", name, err));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} fixtures failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+/// Decodes `qoi_path` and compares it with `raw_path`, then re-encodes the raw pixels
+fn check(
+    qoi_path: &Path,
+    raw_path: &Path,
+    channels: ChannelCount,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let qoi = std::fs::read(qoi_path).map_err(|e| e.to_string())?;
+    let raw = std::fs::read(raw_path).map_err(|e| e.to_string())?;
+
+    let (data, width, _, stored_channels) =
+        decode_auto(&qoi[..]).map_err(|e| format!("failed to decode: {}", e))?;
+    if data != raw || stored_channels != channels {
+        return Err("decoded pixels differ from the raw file".into());
+    }
+
+    let mut encoded = Cursor::new(Vec::new());
+    encode_with_options(&mut encoded, &raw, width as usize, channels, options)
+        .map_err(|e| e.to_string())?;
+    if encoded.into_inner() != qoi {
+        return Err("encoder output has changed".into());
+    }
+
+    Ok(())
+}
+
+/// Parses a header whose fields all have distinct bytes, so that reading any of them in the
+/// host's byte order on a big-endian machine gives a different value
+#[test]
+fn header_is_little_endian() {
+    #[rustfmt::skip]
+    let bytes = [
+        b'q', b'o', b'i', b'v',
+        4,                      // Version
+        0x01, 0x02, 0x03, 0x00, // Width 0x030201
+        0x04, 0x05, 0x06, 0x00, // Height 0x060504
+        3,                      // Channels
+        1,                      // Colorspace
+        0b01,                   // Flags: CRC
+        0x07, 0x08, 0x09, 0x0A, // Compressed size 0x0A090807
+    ];
+    let expected = QoiHeader {
+        width: 0x030201,
+        height: 0x060504,
+        channels: ChannelCount::Rgb,
+        colorspace: Colorspace::Linear,
+        has_crc: true,
+        resets_rows: false,
+        compressed_len: 0x0A090807,
+    };
+
+    assert_eq!(read_header(&bytes[..]).unwrap(), expected);
+}
@@ -0,0 +1,140 @@
+//! The exact bytes of known images, so that a change to the opcodes chosen by the encoder shows
+//! up as a failure rather than as silently different files
+mod common;
+
+use common::{opcodes, raw_stream, round_trip, HEADER_SIZE};
+use qoi_rs::{decode, encode, encode_into_buffer, encode_to_vec, ChannelCount, Rgba};
+use std::io::Cursor;
+
+/// A 4x4 RGBA checkerboard, starting with white
+fn checkerboard() -> Vec<u8> {
+    (0..4 * 4)
+        .flat_map(|i| {
+            let white = (i % 4 + i / 4) % 2 == 0;
+            if white {
+                [0xFF, 0xFF, 0xFF, 0xFF]
+            } else {
+                [0x00, 0x00, 0x00, 0xFF]
+            }
+        })
+        .collect()
+}
+
+#[rustfmt::skip]
+const CHECKERBOARD_QOI: [u8; 46] = [
+    // Magic, version 4, 4x4, RGBA, sRGB, no flags and 26 bytes of opcodes and padding
+    0x71, 0x6F, 0x69, 0x76, 0x04,
+    0x04, 0x00, 0x00, 0x00,
+    0x04, 0x00, 0x00, 0x00,
+    0x04, 0x00, 0x00,
+    0x1A, 0x00, 0x00, 0x00,
+    // QOI_COLOR for white and for black, leaving the alpha unchanged
+    0xFE, 0xFF, 0xFF, 0xFF,
+    0xFE, 0x00, 0x00, 0x00,
+    // Index hits on white (slot 0) and black (slot 63), with a QOI_RUN_8 of 1 at each row start
+    0x00, 0x3F,
+    0x40, 0x00, 0x3F, 0x00,
+    0x40, 0x3F, 0x00, 0x3F,
+    0x40, 0x00, 0x3F, 0x00,
+    // Padding
+    0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn checkerboard_bytes() {
+    let data = checkerboard();
+    assert_eq!(
+        encode_to_vec(&data, 4, ChannelCount::Rgba).unwrap(),
+        CHECKERBOARD_QOI
+    );
+
+    let mut written = Cursor::new(Vec::new());
+    encode(&mut written, &data, 4, ChannelCount::Rgba).unwrap();
+    assert_eq!(written.into_inner(), CHECKERBOARD_QOI);
+
+    round_trip(&data, 4, ChannelCount::Rgba);
+}
+
+/// A single pixel, compared with the initial previous pixel of opaque black and with the index,
+/// whose slots all start out as transparent black
+#[test]
+fn single_pixel_bytes() {
+    let cases: [(Rgba, &[u8]); 6] = [
+        ([0, 0, 0, 255], &[0x40]),
+        ([0, 0, 0, 0], &[0x00]),
+        ([1, 0, 0, 255], &[0xA5]),
+        ([16, 0, 0, 255], &[0xDF, 0x77]),
+        ([0, 0, 0, 254], &[0xE7, 0xBD, 0xEE]),
+        ([200, 100, 50, 25], &[0xFF, 200, 100, 50, 25]),
+    ];
+    for (px, expected) in cases {
+        let qoi = round_trip(&px, 1, ChannelCount::Rgba);
+        assert_eq!(opcodes(&qoi), expected, "{:?}", px);
+    }
+
+    // Without alpha every pixel is opaque, and encodes like the opaque RGBA pixels above
+    let cases: [([u8; 3], &[u8]); 4] = [
+        ([0, 0, 0], &[0x40]),
+        ([1, 0, 0], &[0xA5]),
+        ([16, 0, 0], &[0xDF, 0x77]),
+        ([200, 100, 50], &[0xFE, 200, 100, 50]),
+    ];
+    for (px, expected) in cases {
+        let qoi = round_trip(&px, 1, ChannelCount::Rgb);
+        assert_eq!(opcodes(&qoi), expected, "{:?}", px);
+    }
+}
+
+/// Every `QOI_DIFF_24`, packed by hand as `1110rrrr rgggggbb bbbaaaaa` with each difference biased
+/// by 15. It must decode to the difference it packs, and be what the encoder writes whenever a
+/// smaller diff can't hold the pixel
+#[test]
+fn diff_24_bytes() {
+    const BASE: u8 = 100;
+    let base = [BASE; 4];
+    let mut stream = raw_stream(
+        2,
+        1,
+        ChannelCount::Rgba,
+        0,
+        &[0xFF, BASE, BASE, BASE, BASE, 0, 0, 0],
+    );
+    // After the header and the QOI_COLOR of the base pixel
+    let diff_at = HEADER_SIZE + 5;
+    let mut encoded = [0; 64];
+
+    for vr in -15..=16i32 {
+        for vg in -15..=16i32 {
+            for vb in -15..=16i32 {
+                for va in -15..=16i32 {
+                    let bits = 0b1110 << 20
+                        | ((vr + 15) << 15)
+                        | ((vg + 15) << 10)
+                        | ((vb + 15) << 5)
+                        | (va + 15);
+                    let diff = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+                    stream[diff_at..diff_at + 3].copy_from_slice(&diff);
+                    let px = [
+                        (BASE as i32 + vr) as u8,
+                        (BASE as i32 + vg) as u8,
+                        (BASE as i32 + vb) as u8,
+                        (BASE as i32 + va) as u8,
+                    ];
+
+                    let (decoded, _, _) = decode(&stream[..], ChannelCount::Rgba).unwrap();
+                    assert_eq!(decoded[4..], px, "{:02x?}", diff);
+
+                    // QOI_DIFF_16 holds red from -15 to 16, and green and blue from -7 to 8
+                    let needs_diff_24 =
+                        va != 0 || !(-7..=8).contains(&vg) || !(-7..=8).contains(&vb);
+                    if needs_diff_24 {
+                        let image = [base, px].concat();
+                        let len = encode_into_buffer(&image, 2, ChannelCount::Rgba, &mut encoded)
+                            .unwrap();
+                        assert!(encoded[..len] == stream[..], "{:?}", (vr, vg, vb, va));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Parsing the stream header, and rejecting headers this version can't read
+mod common;
+
+use common::{repeat_pixel, set_compressed_len, HEADER_SIZE, SIZE_FIELD_OFFSET};
+use qoi_rs::{
+    dimensions, encode_to_vec, encode_with_options, read_header, ChannelCount, Colorspace,
+    EncodeOptions, QoiError, MAX_DIMENSION,
+};
+use std::io::Cursor;
+
+/// A valid RGB stream of a 7x5 image
+fn sample() -> Vec<u8> {
+    encode_to_vec(
+        &repeat_pixel([1, 2, 3, 4], 7 * 5, ChannelCount::Rgb),
+        7,
+        ChannelCount::Rgb,
+    )
+    .unwrap()
+}
+
+#[test]
+fn parses_encoded_header() {
+    let qoi = sample();
+    let header = read_header(&qoi[..]).unwrap();
+    assert_eq!((header.width, header.height), (7, 5));
+    assert_eq!(header.channels, ChannelCount::Rgb);
+    assert_eq!(header.colorspace, Colorspace::Srgb);
+    assert!(!header.has_crc && !header.resets_rows);
+    assert_eq!(header.compressed_len as usize, qoi.len() - HEADER_SIZE);
+    assert_eq!(header.stream_len(), qoi.len() as u64);
+    assert_eq!(dimensions(&qoi[..]).unwrap(), (7, 5));
+}
+
+#[test]
+fn parses_options() {
+    let data = repeat_pixel([1, 2, 3, 4], 6, ChannelCount::Rgba);
+    let options = EncodeOptions::new()
+        .colorspace(Colorspace::Linear)
+        .crc(true)
+        .reset_rows(true);
+    let mut qoi = Cursor::new(Vec::new());
+    encode_with_options(&mut qoi, &data, 3, ChannelCount::Rgba, &options).unwrap();
+
+    let header = read_header(&qoi.get_ref()[..]).unwrap();
+    assert_eq!(header.colorspace, Colorspace::Linear);
+    assert!(header.has_crc && header.resets_rows);
+}
+
+/// Overwrites `len` bytes at `offset` of a valid header and returns what `read_header` makes of it
+fn patched(offset: usize, bytes: &[u8]) -> QoiError {
+    let mut qoi = sample();
+    qoi[offset..offset + bytes.len()].copy_from_slice(bytes);
+    read_header(&qoi[..]).unwrap_err()
+}
+
+#[test]
+fn rejects_bad_magic() {
+    assert!(matches!(patched(0, b"\x89PNG"), QoiError::BadMagic(m) if &m == b"\x89PNG"));
+}
+
+#[test]
+fn rejects_other_versions() {
+    // The original layout, which had no version byte
+    assert!(matches!(
+        patched(0, b"qoif"),
+        QoiError::UnsupportedVersion(0)
+    ));
+    assert!(matches!(patched(4, &[3]), QoiError::UnsupportedVersion(3)));
+    assert!(matches!(patched(4, &[5]), QoiError::UnsupportedVersion(5)));
+}
+
+#[test]
+fn rejects_bad_dimensions() {
+    assert!(matches!(
+        patched(5, &0u32.to_le_bytes()),
+        QoiError::ZeroDimension
+    ));
+    assert!(matches!(
+        patched(9, &0u32.to_le_bytes()),
+        QoiError::ZeroDimension
+    ));
+    let too_large = (MAX_DIMENSION + 1).to_le_bytes();
+    assert!(matches!(
+        patched(5, &too_large),
+        QoiError::DimensionTooLarge
+    ));
+    assert!(matches!(
+        patched(9, &too_large),
+        QoiError::DimensionTooLarge
+    ));
+}
+
+#[test]
+fn rejects_bad_fields() {
+    // There is no two-channel (gray and alpha) layout
+    assert!(matches!(patched(13, &[2]), QoiError::BadChannelCount(2)));
+    assert!(matches!(patched(13, &[0]), QoiError::BadChannelCount(0)));
+    assert!(matches!(patched(14, &[2]), QoiError::BadColorspace(2)));
+    assert!(matches!(
+        patched(15, &[0x80]),
+        QoiError::UnsupportedFlags(0x80)
+    ));
+}
+
+#[test]
+fn rejects_truncated_header() {
+    let qoi = sample();
+    for len in 0..HEADER_SIZE {
+        assert!(
+            matches!(read_header(&qoi[..len]), Err(QoiError::UnexpectedEof)),
+            "accepted a {} byte header",
+            len
+        );
+    }
+}
+
+/// A size too small for any stream of the image's dimensions, e.g. zeroed, is refused up front
+#[test]
+fn rejects_short_compressed_len() {
+    // 35 pixels take at least a QOI_RUN_16, and then the padding
+    for declared in [0u32, 1, 5] {
+        assert!(matches!(
+            patched(SIZE_FIELD_OFFSET, &declared.to_le_bytes()),
+            QoiError::SizeMismatch { declared: d, actual: 6 } if d == declared
+        ));
+    }
+    let mut qoi = sample();
+    set_compressed_len(&mut qoi, 6);
+    assert_eq!(read_header(&qoi[..]).unwrap().compressed_len, 6);
+
+    // A million pixels take 121 runs of the maximum length, and a QOI_RUN_16 for the rest
+    let mut qoi = sample();
+    qoi[5..9].copy_from_slice(&1000u32.to_le_bytes());
+    qoi[9..13].copy_from_slice(&1000u32.to_le_bytes());
+    let min_len: u32 = 121 * 2 + 2 + 4;
+    set_compressed_len(&mut qoi, min_len - 1);
+    assert!(matches!(
+        read_header(&qoi[..]),
+        Err(QoiError::SizeMismatch { actual: 248, .. })
+    ));
+    set_compressed_len(&mut qoi, min_len);
+    assert!(read_header(&qoi[..]).is_ok());
+}
@@ -0,0 +1,162 @@
+//! Decoding to and encoding from pixel layouts other than interleaved RGBA
+mod common;
+
+use common::{Rng, ALL_CHANNELS};
+use qoi_rs::{
+    decode, decode_converted, decode_planar, decode_with_options, decode_with_order, encode_planar,
+    encode_to_vec, encode_with_options, ChannelCount, DecodeOptions, EncodeOptions, PixelOrder,
+    QoiError,
+};
+use std::io::Cursor;
+
+const WIDTH: usize = 23;
+const HEIGHT: usize = 9;
+
+/// A random image and its stream
+fn image(channels: ChannelCount, seed: u32) -> (Vec<u8>, Vec<u8>) {
+    let data = Rng::new(seed).bytes(WIDTH * HEIGHT * channels.bytes_per_pixel());
+    let qoi = encode_to_vec(&data, WIDTH, channels).unwrap();
+    (data, qoi)
+}
+
+/// BGRA swaps red and blue in every pixel and leaves gray alone
+#[test]
+fn decodes_in_order() {
+    for channels in ALL_CHANNELS {
+        let (data, qoi) = image(channels, 0x0BD3);
+        let (decoded, _, _) = decode(&qoi[..], channels).unwrap();
+        assert!(decoded == data);
+
+        let (rgba, w, h) = decode_with_order(&qoi[..], channels, PixelOrder::Rgba).unwrap();
+        assert_eq!((w, h), (WIDTH as u32, HEIGHT as u32));
+        assert!(rgba == decoded, "{:?}", channels);
+
+        let mut swizzled = decoded;
+        if channels != ChannelCount::Gray {
+            for px in swizzled.chunks_exact_mut(channels.bytes_per_pixel()) {
+                px.swap(0, 2);
+            }
+        }
+        let (bgra, _, _) = decode_with_order(&qoi[..], channels, PixelOrder::Bgra).unwrap();
+        assert!(bgra == swizzled, "{:?}", channels);
+    }
+}
+
+/// Planes encode to the same stream as the RGBA image they interleave to
+#[test]
+fn encodes_planes() {
+    let mut rng = Rng::new(0x9A4E);
+    for channels in ALL_CHANNELS {
+        let (color, _) = image(channels, rng.next_u32());
+        let alpha = rng.bytes(WIDTH * HEIGHT);
+        let interleaved: Vec<u8> = color
+            .chunks_exact(channels.bytes_per_pixel())
+            .zip(&alpha)
+            .flat_map(|(px, &a)| match *px {
+                [v] => [v, v, v, a],
+                [r, g, b] | [r, g, b, _] => [r, g, b, a],
+                _ => unreachable!(),
+            })
+            .collect();
+
+        let mut written = Cursor::new(Vec::new());
+        let len = encode_planar(&mut written, &color, &alpha, WIDTH, channels).unwrap();
+        let written = written.into_inner();
+        assert_eq!(len as usize, written.len());
+        assert!(
+            written == encode_to_vec(&interleaved, WIDTH, ChannelCount::Rgba).unwrap(),
+            "{:?}",
+            channels
+        );
+
+        let err = encode_planar(
+            Cursor::new(Vec::new()),
+            &color,
+            &alpha[1..],
+            WIDTH,
+            channels,
+        );
+        assert!(matches!(
+            err,
+            Err(QoiError::DataTooShort { required, available })
+                if required == WIDTH * HEIGHT && available == WIDTH * HEIGHT - 1
+        ));
+    }
+}
+
+/// The alpha given to RGB and gray pixels, and only to them, when decoding to RGBA
+#[test]
+fn decodes_with_alpha() {
+    for stored in ALL_CHANNELS {
+        let (_, qoi) = image(stored, 0xA1FA);
+        for output in ALL_CHANNELS {
+            let (converted, _, _) = decode_converted(&qoi[..], output).unwrap();
+            let defaults = decode_with_options(&qoi[..], output, &DecodeOptions::new()).unwrap();
+            assert!(defaults.0 == converted);
+            assert_eq!((defaults.1, defaults.2), (WIDTH as u32, HEIGHT as u32));
+
+            let options = DecodeOptions::new().rgb_to_rgba_alpha(0x40);
+            let (decoded, _, _) = decode_with_options(&qoi[..], output, &options).unwrap();
+            let mut expected = converted;
+            if output == ChannelCount::Rgba && stored != ChannelCount::Rgba {
+                for px in expected.chunks_exact_mut(4) {
+                    px[3] = 0x40;
+                }
+            }
+            assert!(decoded == expected, "{:?} to {:?}", stored, output);
+        }
+    }
+}
+
+/// Flipping on the way in and again on the way out gives back the image, and either flip alone
+/// reverses its rows
+#[test]
+fn flips_rows() {
+    for channels in ALL_CHANNELS {
+        let (data, qoi) = image(channels, 0xF11B);
+        let flipped: Vec<u8> = data
+            .chunks_exact(WIDTH * channels.bytes_per_pixel())
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+
+        let flip = DecodeOptions::new().flip_vertical(true);
+        let (decoded, _, _) = decode_with_options(&qoi[..], channels, &flip).unwrap();
+        assert!(decoded == flipped, "{:?}", channels);
+
+        let mut written = Cursor::new(Vec::new());
+        let options = EncodeOptions::new().flip_vertical(true);
+        encode_with_options(&mut written, &data, WIDTH, channels, &options).unwrap();
+        let written = written.into_inner();
+        assert!(written == encode_to_vec(&flipped, WIDTH, channels).unwrap());
+
+        let (decoded, _, _) = decode_with_options(&written[..], channels, &flip).unwrap();
+        assert!(decoded == data, "{:?}", channels);
+    }
+}
+
+/// Each plane holds one channel of the interleaved image, with gray spread over all three colors
+#[test]
+fn decodes_planes() {
+    for channels in ALL_CHANNELS {
+        let (_, qoi) = image(channels, 0x91A7);
+        let (interleaved, _, _) = decode(&qoi[..], channels).unwrap();
+        let plane = |channel: usize| -> Vec<u8> {
+            interleaved
+                .chunks_exact(channels.bytes_per_pixel())
+                .map(|px| px[channel.min(px.len() - 1)])
+                .collect()
+        };
+
+        let planes = decode_planar(&qoi[..], channels).unwrap();
+        assert_eq!((planes.width, planes.height), (WIDTH as u32, HEIGHT as u32));
+        assert!(planes.red == plane(0), "{:?}", channels);
+        assert!(planes.green == plane(1), "{:?}", channels);
+        assert!(planes.blue == plane(2), "{:?}", channels);
+        assert_eq!(
+            planes.alpha,
+            (channels == ChannelCount::Rgba).then(|| plane(3))
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Conversions between PNG and QOI files
+#![cfg(feature = "png")]
+mod common;
+
+use common::{Rng, ALL_CHANNELS};
+use png::{BitDepth, ColorType};
+use qoi_rs::{
+    decode16, decode16_auto, decode_auto, encode16_to_vec, encode_to_vec, png_to_qoi, qoi_to_png,
+    ChannelCount, Rgba16,
+};
+
+const WIDTH: u32 = 7;
+const HEIGHT: u32 = 5;
+
+/// The PNG color types which have a channel count of their own
+const COLORS: [(ColorType, ChannelCount); 3] = [
+    (ColorType::Grayscale, ChannelCount::Gray),
+    (ColorType::Rgb, ChannelCount::Rgb),
+    (ColorType::Rgba, ChannelCount::Rgba),
+];
+
+fn encode_png(data: &[u8], color: ColorType, depth: BitDepth) -> Vec<u8> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+    writer.finish().unwrap();
+    png
+}
+
+/// Returns (bytes as stored in the PNG, color type, bit depth)
+fn decode_png(png: &[u8]) -> (Vec<u8>, ColorType, BitDepth) {
+    let mut reader = png::Decoder::new(png).read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (WIDTH, HEIGHT));
+    buf.truncate(info.buffer_size());
+    (buf, info.color_type, info.bit_depth)
+}
+
+#[test]
+fn round_trip_8_bit() {
+    let mut rng = Rng::new(0x9196);
+    for (color, channels) in COLORS {
+        let data = rng.bytes((WIDTH * HEIGHT) as usize * channels.bytes_per_pixel());
+        let qoi = png_to_qoi(&encode_png(&data, color, BitDepth::Eight)).unwrap();
+        assert!(qoi == encode_to_vec(&data, WIDTH as usize, channels).unwrap());
+
+        let (decoded, stored_color, depth) = decode_png(&qoi_to_png(&qoi, None).unwrap());
+        assert_eq!((stored_color, depth), (color, BitDepth::Eight));
+        assert!(decoded == data, "{:?}", color);
+    }
+}
+
+/// Gray with alpha has no channel count of its own, so it becomes RGBA
+#[test]
+fn gray_alpha_becomes_rgba() {
+    let data = Rng::new(0x6A).bytes((WIDTH * HEIGHT * 2) as usize);
+    let qoi = png_to_qoi(&encode_png(
+        &data,
+        ColorType::GrayscaleAlpha,
+        BitDepth::Eight,
+    ))
+    .unwrap();
+
+    let (decoded, _, _, channels) = decode_auto(&qoi[..]).unwrap();
+    assert_eq!(channels, ChannelCount::Rgba);
+    let expected: Vec<u8> = data
+        .chunks_exact(2)
+        .flat_map(|px| [px[0], px[0], px[0], px[1]])
+        .collect();
+    assert!(decoded == expected);
+}
+
+#[test]
+fn round_trip_16_bit() {
+    let mut rng = Rng::new(0x1616);
+    for (color, channels) in COLORS {
+        // Big endian, as PNG stores them
+        let data = rng.bytes((WIDTH * HEIGHT) as usize * channels.bytes_per_pixel() * 2);
+        let qoi = png_to_qoi(&encode_png(&data, color, BitDepth::Sixteen)).unwrap();
+
+        let (samples, width, height, stored) = decode16_auto(&qoi[..]).unwrap();
+        assert_eq!((width, height, stored), (WIDTH, HEIGHT, channels));
+        let expected: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(samples, expected);
+
+        let (decoded, stored_color, depth) = decode_png(&qoi_to_png(&qoi, None).unwrap());
+        assert_eq!((stored_color, depth), (color, BitDepth::Sixteen));
+        assert!(decoded == data, "{:?}", color);
+    }
+}
+
+/// The channel count given overrides the stored one, converting like `decode_converted`
+#[test]
+fn converts_8_bit_channels() {
+    let rgb = Rng::new(0x0C8).bytes((WIDTH * HEIGHT * 3) as usize);
+    let qoi = encode_to_vec(&rgb, WIDTH as usize, ChannelCount::Rgb).unwrap();
+
+    let (rgba, color, _) = decode_png(&qoi_to_png(&qoi, Some(ChannelCount::Rgba)).unwrap());
+    assert_eq!(color, ColorType::Rgba);
+    let expected: Vec<u8> = rgb
+        .chunks_exact(3)
+        .flat_map(|px| [px[0], px[1], px[2], 255])
+        .collect();
+    assert!(rgba == expected);
+
+    let (gray, color, _) = decode_png(&qoi_to_png(&qoi, Some(ChannelCount::Gray)).unwrap());
+    assert_eq!(color, ColorType::Grayscale);
+    let expected: Vec<u8> = rgb.chunks_exact(3).map(|px| px[0]).collect();
+    assert!(gray == expected);
+}
+
+/// 16-bit files convert to another channel count the same way 8-bit ones do
+#[test]
+fn converts_16_bit_channels() {
+    let rgba: [Rgba16; 2] = [
+        [0x1234, 0x5678, 0x9ABC, 0xDEF0],
+        [0xFFFF, 0x0001, 0x8000, 0x4242],
+    ];
+    for stored in ALL_CHANNELS {
+        // What the stored pixels read back as in RGBA
+        let stored_rgba = rgba.map(|[r, g, b, a]| match stored {
+            ChannelCount::Gray => [r, r, r, u16::MAX],
+            ChannelCount::Rgb => [r, g, b, u16::MAX],
+            ChannelCount::Rgba => [r, g, b, a],
+        });
+        let samples: Vec<u16> = rgba
+            .iter()
+            .flat_map(|px| &px[..stored as usize])
+            .copied()
+            .collect();
+        let qoi = encode16_to_vec(&samples, 2, stored).unwrap();
+
+        for requested in ALL_CHANNELS {
+            let png = qoi_to_png(&qoi, Some(requested)).unwrap();
+            let (decoded, width, height) =
+                decode16(&png_to_qoi(&png).unwrap()[..], requested).unwrap();
+            assert_eq!((width, height), (2, 1));
+            let expected: Vec<u16> = stored_rgba
+                .iter()
+                .flat_map(|px| &px[..requested as usize])
+                .copied()
+                .collect();
+            assert_eq!(decoded, expected, "{:?} to {:?}", stored, requested);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! The variant of the format with 16 bits per channel
+mod common;
+
+use common::ALL_CHANNELS;
+use qoi_rs::{
+    decode16, decode16_auto, encode16_to_vec, is_qoi16, ChannelCount, QoiError, MAX_DIMENSION,
+};
+
+/// Encodes 16-bit samples and checks that both decoders give them back unchanged
+fn round_trip16(data: &[u16], width: usize, channels: ChannelCount) -> Vec<u8> {
+    let qoi = encode16_to_vec(data, width, channels).unwrap();
+    assert!(is_qoi16(&qoi));
+    let height = (data.len() / (width * channels as usize)) as u32;
+
+    let (decoded, w, h) = decode16(&qoi[..], channels).unwrap();
+    assert_eq!((w, h), (width as u32, height));
+    assert!(decoded == data, "decoded samples differ from the input");
+
+    let (decoded, _, _, stored) = decode16_auto(&qoi[..]).unwrap();
+    assert_eq!(stored, channels);
+    assert!(decoded == data);
+
+    qoi
+}
+
+/// Smooth gradients, whose steps are small enough for the diff opcodes, and steep ones which
+/// wrap from 65535 to 0
+#[test]
+fn gradients() {
+    for channels in ALL_CHANNELS {
+        for step in [1u16, 7, 300, 4099] {
+            let data: Vec<u16> = (0..48u16)
+                .flat_map(|y| (0..200u16).map(move |x| (x, y)))
+                .flat_map(|(x, y)| {
+                    let px = [
+                        x.wrapping_mul(step),
+                        y.wrapping_mul(step),
+                        (x + y).wrapping_mul(step),
+                        u16::MAX - x.wrapping_mul(step),
+                    ];
+                    px[..channels as usize].to_vec()
+                })
+                .collect();
+            let qoi = round_trip16(&data, 200, channels);
+
+            // Each sample takes 2 bytes when stored raw
+            if step == 1 {
+                assert!(qoi.len() < data.len() * 2);
+            }
+        }
+    }
+}
+
+#[test]
+fn not_an_8_bit_stream() {
+    let qoi = encode16_to_vec(&[0x1234; 3 * 2], 2, ChannelCount::Rgb).unwrap();
+    assert!(qoi_rs::decode(&qoi[..], ChannelCount::Rgb).is_err());
+}
+
+#[test]
+fn rejects_other_channel_count() {
+    for stored in ALL_CHANNELS {
+        let qoi = encode16_to_vec(&vec![0x1234; 4 * stored as usize], 2, stored).unwrap();
+        for requested in ALL_CHANNELS.into_iter().filter(|&c| c != stored) {
+            let err = decode16(&qoi[..], requested).unwrap_err();
+            assert!(matches!(
+                err,
+                QoiError::ChannelMismatch { expected, requested: r } if expected == stored && r == requested
+            ));
+        }
+    }
+}
+
+#[test]
+fn rejects_over_limit() {
+    // The largest dimensions, whose size overflows a 32-bit usize several times over
+    let mut qoi = encode16_to_vec(&[0; 4], 1, ChannelCount::Rgba).unwrap();
+    qoi[5..9].copy_from_slice(&MAX_DIMENSION.to_le_bytes());
+    qoi[9..13].copy_from_slice(&MAX_DIMENSION.to_le_bytes());
+    let err = decode16(&qoi[..], ChannelCount::Rgba).unwrap_err();
+    assert!(matches!(
+        err,
+        QoiError::TooLarge {
+            limit: 0x4000_0000,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn rejects_run_past_end() {
+    // Opaque black throughout is a single run of 4, after the 18 byte header
+    let mut qoi = encode16_to_vec(&[0, 0, 0, u16::MAX].repeat(4), 2, ChannelCount::Rgba).unwrap();
+    assert_eq!(qoi[18..], [0x43, 0, 0, 0, 0]);
+    qoi[18] = 0x45;
+    assert!(matches!(
+        decode16(&qoi[..], ChannelCount::Rgba),
+        Err(QoiError::RunPastEnd)
+    ));
+}
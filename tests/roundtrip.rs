@@ -0,0 +1,274 @@
+//! Encoding then decoding gives back the original pixels, across channel counts, image sizes and
+//! the opcodes each kind of image exercises
+mod common;
+
+use common::{opcodes, raw_stream, repeat_pixel, round_trip, Rng, ALL_CHANNELS};
+use qoi_rs::{
+    decode, decode_and_consumed, encode_to_vec, encode_with_options, encode_with_progress,
+    encode_with_stats, max_encoded_size, min_encoded_size, ChannelCount, EncodeOptions, QoiImage,
+};
+use std::io::{self, Cursor};
+
+#[test]
+fn random_images() {
+    let mut rng = Rng::new(0x5EED);
+    for channels in ALL_CHANNELS {
+        for _ in 0..200 {
+            let width = rng.range(1..=40);
+            let height = rng.range(1..=40);
+            let data = rng.bytes(width * height * channels.bytes_per_pixel());
+            round_trip(&data, width, channels);
+        }
+    }
+}
+
+/// Runs, small steps and large steps mixed, so that every opcode follows every other
+#[test]
+fn mixed_images() {
+    let mut rng = Rng::new(0xD1FF);
+    for channels in ALL_CHANNELS {
+        for _ in 0..200 {
+            let width = rng.range(1..=40);
+            let height = rng.range(1..=40);
+            let mut px = [0u8; 4];
+            let mut data = Vec::new();
+            for _ in 0..width * height {
+                match rng.range(0..=3) {
+                    0 => {}
+                    1 => px = rng.next_u32().to_le_bytes(),
+                    step => {
+                        let max = if step == 2 { 2 } else { 17 };
+                        for channel in &mut px {
+                            let delta = rng.range(0..=2 * max) as i32 - max as i32;
+                            *channel = channel.wrapping_add_signed(delta as i8);
+                        }
+                    }
+                }
+                data.extend_from_slice(&px[..channels.bytes_per_pixel()]);
+            }
+            round_trip(&data, width, channels);
+        }
+    }
+}
+
+#[test]
+fn solid_image_is_runs() {
+    for channels in ALL_CHANNELS {
+        let data = repeat_pixel([90, 140, 200, 255], 300 * 300, channels);
+        let qoi = round_trip(&data, 300, channels);
+
+        // The first pixel differs from the initial previous pixel, and the rest are runs of the
+        // maximum length and a remainder
+        let stats = encode_with_stats(io::sink(), &data, 300, channels).unwrap();
+        assert_eq!(stats.run_8.pixels + stats.run_16.pixels, 300 * 300 - 1);
+        assert!(opcodes(&qoi).len() <= 5 + 2 * (300 * 300 / 0x2020 + 1));
+    }
+}
+
+#[test]
+fn noise_is_full_colors() {
+    let mut rng = Rng::new(0xC0102);
+    let data = rng.bytes(64 * 64 * 4);
+    round_trip(&data, 64, ChannelCount::Rgba);
+
+    let stats = encode_with_stats(io::sink(), &data, 64, ChannelCount::Rgba).unwrap();
+    assert!(stats.color.pixels > 64 * 64 * 99 / 100);
+}
+
+/// More colors than the index has slots, so that slots are both hit and overwritten
+#[test]
+fn palette_uses_index() {
+    let mut rng = Rng::new(0xCAFE);
+    let palette: Vec<u32> = (0..70).map(|_| rng.next_u32()).collect();
+    for channels in ALL_CHANNELS {
+        let data: Vec<u8> = (0..48 * 48)
+            .flat_map(|_| {
+                let px = palette[rng.range(0..=palette.len() - 1)].to_le_bytes();
+                px[..channels.bytes_per_pixel()].to_vec()
+            })
+            .collect();
+        round_trip(&data, 48, channels);
+
+        let stats = encode_with_stats(io::sink(), &data, 48, channels).unwrap();
+        assert!(stats.index.count > 48 * 48 / 4);
+    }
+}
+
+/// Gradients which wrap from 255 to 0 and back several times along each row
+#[test]
+fn wrapping_gradients() {
+    for channels in ALL_CHANNELS {
+        let data: Vec<u8> = (0..64usize)
+            .flat_map(|y| (0..256usize).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let px = [
+                    (x * 5) as u8,
+                    255u8.wrapping_sub((x * 3) as u8),
+                    (x + y * 7) as u8,
+                    (255 - x) as u8,
+                ];
+                px[..channels.bytes_per_pixel()].to_vec()
+            })
+            .collect();
+        round_trip(&data, 256, channels);
+    }
+}
+
+/// Differences are taken modulo 256. This crate's encoder switches to a full color at the wrap,
+/// so the wrapping diffs are written by hand
+#[test]
+fn diffs_wrap() {
+    #[rustfmt::skip]
+    let qoi = raw_stream(4, 1, ChannelCount::Rgba, 0, &[
+        0xFF, 255, 0, 255, 0, // QOI_COLOR
+        0xA2,                 // QOI_DIFF_8 of +1, -1, +1
+        0xC0, 0xF0,           // QOI_DIFF_16 of -15, +8, -7
+        0xE7, 0xBD, 0xE0,     // QOI_DIFF_24 of 0, 0, 0, -15
+    ]);
+    let (data, _, _) = decode(&qoi[..], ChannelCount::Rgba).unwrap();
+    #[rustfmt::skip]
+    assert_eq!(data, [
+        255, 0, 255, 0,
+        0, 255, 0, 0,
+        241, 7, 249, 0,
+        241, 7, 249, 241,
+    ]);
+}
+
+/// The run at the end of an RGBA image is flushed before the padding
+#[test]
+fn rgba_ends_in_run() {
+    let mut rng = Rng::new(0x20);
+    let mut data = rng.bytes(10 * 4);
+    data.extend(repeat_pixel([12, 34, 56, 78], 6, ChannelCount::Rgba));
+    let qoi = round_trip(&data, 4, ChannelCount::Rgba);
+
+    // The first of the repeated pixels is a full color, and the other 5 a QOI_RUN_8
+    assert_eq!(*opcodes(&qoi).last().unwrap(), 0x40 | (5 - 1));
+}
+
+/// Progress never goes backwards and ends at the total, without changing what is written
+#[test]
+fn progress_is_reported() {
+    let mut rng = Rng::new(0x9A55);
+    for channels in ALL_CHANNELS {
+        let (width, height) = (17, 11);
+        let data = rng.bytes(width * height * channels.bytes_per_pixel());
+
+        let mut calls = Vec::new();
+        let mut written = Cursor::new(Vec::new());
+        let len = encode_with_progress(&mut written, &data, width, channels, |done, total| {
+            calls.push((done, total))
+        })
+        .unwrap();
+        let written = written.into_inner();
+        assert_eq!(len as usize, written.len());
+        assert!(written == encode_to_vec(&data, width, channels).unwrap());
+
+        // Once before each row, and once when finished
+        assert_eq!(calls.len(), height + 1);
+        assert!(calls.iter().all(|&(_, total)| total == width * height));
+        assert!(calls.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(calls[0].0, 0);
+        assert_eq!(*calls.last().unwrap(), (width * height, width * height));
+    }
+}
+
+/// The bound holds for noise, the image which compresses worst, and saturates rather than
+/// overflowing
+#[test]
+fn max_encoded_size_bounds() {
+    let mut rng = Rng::new(0xB0B0);
+    for channels in ALL_CHANNELS {
+        let data = rng.bytes(31 * 7 * channels.bytes_per_pixel());
+        let qoi = round_trip(&data, 31, channels);
+        assert!(qoi.len() <= max_encoded_size(31, 7, channels));
+        assert_eq!(max_encoded_size(u32::MAX, u32::MAX, channels), usize::MAX);
+    }
+}
+
+/// Streams written back to back are read one at a time, each leaving the reader at the next
+#[test]
+fn consumed_streams_back_to_back() {
+    let mut rng = Rng::new(0xC0A7);
+    let first = rng.bytes(9 * 4 * 3);
+    let second = rng.bytes(5 * 6 * 4);
+    for crc in [false, true] {
+        let mut joined = Cursor::new(Vec::new());
+        let options = EncodeOptions::new().crc(crc);
+        let first_len =
+            encode_with_options(&mut joined, &first, 9, ChannelCount::Rgb, &options).unwrap();
+        let mut joined = joined.into_inner();
+        let second_qoi = encode_to_vec(&second, 5, ChannelCount::Rgba).unwrap();
+        joined.extend_from_slice(&second_qoi);
+
+        let mut reader = &joined[..];
+        let (decoded, width, height, consumed) =
+            decode_and_consumed(&mut reader, ChannelCount::Rgb).unwrap();
+        assert_eq!((width, height), (9, 4));
+        assert!(decoded == first);
+        assert_eq!(consumed, first_len);
+        assert_eq!(reader.len(), second_qoi.len());
+
+        let (decoded, width, height, consumed) =
+            decode_and_consumed(&mut reader, ChannelCount::Rgba).unwrap();
+        assert_eq!((width, height), (5, 6));
+        assert!(decoded == second);
+        assert_eq!(consumed, second_qoi.len() as u64);
+        assert!(reader.is_empty());
+    }
+}
+
+#[test]
+fn qoi_image_round_trip() {
+    let mut rng = Rng::new(0x1A6E);
+    for channels in ALL_CHANNELS {
+        let data = rng.bytes(6 * 4 * channels.bytes_per_pixel());
+        let image = QoiImage::new(data.clone(), 6, channels).unwrap();
+        assert_eq!((image.width(), image.height()), (6, 4));
+        assert_eq!(image.channels(), channels);
+        assert!(image.pixels() == data);
+
+        let row_len = 6 * channels.bytes_per_pixel();
+        assert!(image.row(2) == &data[2 * row_len..3 * row_len]);
+        let px =
+            &data[2 * row_len + 5 * channels.bytes_per_pixel()..][..channels.bytes_per_pixel()];
+        let expected = match *px {
+            [v] => [v, v, v, 255],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        };
+        assert_eq!(image.get_pixel(5, 2), expected);
+
+        let qoi = image.encode();
+        assert!(qoi == encode_to_vec(&data, 6, channels).unwrap());
+        let decoded = QoiImage::try_from(&qoi[..]).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (6, 4));
+        assert_eq!(decoded.channels(), channels);
+        assert!(decoded.into_pixels() == data);
+    }
+}
+
+/// An opaque black image is the one which reaches the bound, and other solid images stay above
+/// it. The largest dimensions don't overflow
+#[test]
+fn min_encoded_size_bounds() {
+    for (width, height) in [(1, 1), (33, 1), (300, 300), (0x2020, 3)] {
+        let min = min_encoded_size(width, height);
+        for channels in ALL_CHANNELS {
+            for px in [[0, 0, 0, 255], [90, 140, 200, 255]] {
+                let data = repeat_pixel(px, (width * height) as usize, channels);
+                let qoi = round_trip(&data, width as usize, channels);
+                assert!(min <= qoi.len(), "{}x{}", width, height);
+                if px == [0, 0, 0, 255] {
+                    assert_eq!(min, qoi.len(), "{}x{}", width, height);
+                }
+            }
+        }
+    }
+
+    let largest = min_encoded_size(u32::MAX, u32::MAX);
+    assert!(largest > min_encoded_size(u32::MAX, 1));
+    assert!(largest <= max_encoded_size(u32::MAX, u32::MAX, ChannelCount::Gray));
+}
@@ -0,0 +1,167 @@
+//! Runs of repeated pixels, at the limits of `QOI_RUN_8` and `QOI_RUN_16`
+mod common;
+
+use common::{opcodes, repeat_pixel, round_trip, Rng, ALL_CHANNELS, HEADER_SIZE};
+use qoi_rs::{
+    encode, encode_from_iter, encode_into_buffer, encode_strided, encode_to_vec, encode_with_stats,
+    max_encoded_size, ChannelCount, LineEncoder,
+};
+use std::io::Cursor;
+
+/// Longest run a single `QOI_RUN_16` holds
+const MAX_RUN_LENGTH: usize = 0x2020;
+
+/// A full color followed by `run` more copies of it, as a single row
+fn run_after_color(run: usize, channels: ChannelCount) -> Vec<u8> {
+    repeat_pixel([90, 140, 200, 255], 1 + run, channels)
+}
+
+/// The opcodes an RGBA image of a color and then a run of `run` encodes to, after the color
+fn run_opcodes(run: usize) -> Vec<u8> {
+    let data = run_after_color(run, ChannelCount::Rgba);
+    let qoi = round_trip(&data, 1 + run, ChannelCount::Rgba);
+    let opcodes = opcodes(&qoi);
+    assert_eq!(opcodes[..4], [0xFE, 90, 140, 200]);
+    opcodes[4..].to_vec()
+}
+
+#[test]
+fn max_length_runs() {
+    for channels in ALL_CHANNELS {
+        for run in [MAX_RUN_LENGTH, MAX_RUN_LENGTH + 1] {
+            let data = run_after_color(run, channels);
+            round_trip(&data, 1 + run, channels);
+        }
+    }
+
+    // A full run, then one which starts over
+    assert_eq!(run_opcodes(MAX_RUN_LENGTH), [0x7F, 0xFF]);
+    assert_eq!(run_opcodes(MAX_RUN_LENGTH + 1), [0x7F, 0xFF, 0x40]);
+}
+
+/// `QOI_RUN_8` holds runs of up to 32, and `QOI_RUN_16` picks up from 33
+#[test]
+fn short_and_long_runs() {
+    for channels in ALL_CHANNELS {
+        for run in 1..=70 {
+            let data = run_after_color(run, channels);
+            round_trip(&data, 1 + run, channels);
+        }
+    }
+
+    assert_eq!(run_opcodes(1), [0x40]);
+    assert_eq!(run_opcodes(32), [0x5F]);
+    assert_eq!(run_opcodes(33), [0x60, 0x00]);
+    assert_eq!(run_opcodes(34), [0x60, 0x01]);
+    assert_eq!(run_opcodes(33 + 0xFF), [0x60, 0xFF]);
+    assert_eq!(run_opcodes(33 + 0x100), [0x61, 0x00]);
+    assert_eq!(run_opcodes(MAX_RUN_LENGTH), [0x7F, 0xFF]);
+}
+
+/// Leading pixels equal to the initial previous pixel, opaque black, are a run from the start
+#[test]
+fn leading_black_runs() {
+    for channels in ALL_CHANNELS {
+        for run in [1, 2, 32, 33, 34, 100, MAX_RUN_LENGTH, MAX_RUN_LENGTH + 1] {
+            let mut data = repeat_pixel([0, 0, 0, 255], run, channels);
+            data.extend(repeat_pixel([90, 140, 200, 255], 3, channels));
+            let qoi = round_trip(&data, run + 3, channels);
+            // Either QOI_RUN_8 or QOI_RUN_16
+            assert_eq!(opcodes(&qoi)[0] & 0xC0, 0x40, "{:?} {}", channels, run);
+        }
+    }
+
+    let black = repeat_pixel([0, 0, 0, 255], 64 * 64, ChannelCount::Rgba);
+    let qoi = round_trip(&black, 64, ChannelCount::Rgba);
+    assert_eq!(qoi[HEADER_SIZE..], [0x6F, 0xDF, 0x00, 0x00, 0x00, 0x00]);
+}
+
+/// The opcodes of a run of `run` pixels: as many full `QOI_RUN_16` as fit, then the remainder
+fn expected_run(run: usize) -> Vec<u8> {
+    let mut opcodes = [0x7F, 0xFF].repeat(run / MAX_RUN_LENGTH);
+    match run % MAX_RUN_LENGTH {
+        0 => {}
+        rest if rest <= 32 => opcodes.push(0x40 | (rest - 1) as u8),
+        rest => opcodes.extend_from_slice(&[0x60 | ((rest - 33) >> 8) as u8, (rest - 33) as u8]),
+    }
+    opcodes
+}
+
+/// Encodes with every encoder which writes the same stream as `encode_to_vec`, and checks that
+/// they all agree
+fn encode_all(data: &[u8], width: usize, channels: ChannelCount) -> Vec<u8> {
+    let expected = encode_to_vec(data, width, channels).unwrap();
+    let row_len = width * channels.bytes_per_pixel();
+    let height = data.len() / row_len;
+
+    let mut written = Cursor::new(Vec::new());
+    encode(&mut written, data, width, channels).unwrap();
+    assert!(written.into_inner() == expected, "encode");
+
+    let mut written = Vec::new();
+    encode_with_stats(&mut written, data, width, channels).unwrap();
+    assert!(written == expected, "encode_with_stats");
+
+    let mut buf = vec![0; max_encoded_size(width as u32, height as u32, channels)];
+    let len = encode_into_buffer(data, width, channels, &mut buf).unwrap();
+    assert!(buf[..len] == expected, "encode_into_buffer");
+
+    let pixels = data
+        .chunks_exact(channels.bytes_per_pixel())
+        .map(|px| match *px {
+            [v] => [v, v, v, 255],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        });
+    let mut written = Cursor::new(Vec::new());
+    encode_from_iter(&mut written, pixels, width, height, channels).unwrap();
+    assert!(written.into_inner() == expected, "encode_from_iter");
+
+    let mut written = Vec::new();
+    let mut encoder = LineEncoder::new(&mut written, width, height, channels).unwrap();
+    for row in data.chunks_exact(row_len) {
+        encoder.write_line(row).unwrap();
+    }
+    encoder.finish().unwrap();
+    assert!(written == expected, "LineEncoder");
+
+    // Rows with a gap between them, which holds a different color
+    let strided: Vec<u8> = data
+        .chunks_exact(row_len)
+        .flat_map(|row| [row, &[0x55; 5]].concat())
+        .collect();
+    let mut written = Cursor::new(Vec::new());
+    encode_strided(&mut written, &strided, width, height, row_len + 5, channels).unwrap();
+    assert!(written.into_inner() == expected, "encode_strided");
+
+    expected
+}
+
+/// The run at the end of an image is written out before the padding, whichever encoder runs
+#[test]
+fn trailing_runs() {
+    let mut rng = Rng::new(0x7A11);
+    for channels in [ChannelCount::Rgb, ChannelCount::Rgba] {
+        for run in [1, 2, 32, 33, 34, 300, MAX_RUN_LENGTH, MAX_RUN_LENGTH + 40] {
+            // Random pixels, then the trailing color and `run` more copies of it
+            let mut data = rng.bytes(37 * channels.bytes_per_pixel());
+            data.extend(repeat_pixel([7, 7, 7, 7], 1 + run, channels));
+            // As a single row, and as two rows where that divides evenly
+            let pixels = 38 + run;
+            for width in [pixels, pixels / 2]
+                .into_iter()
+                .filter(|&w| pixels.is_multiple_of(w))
+            {
+                let qoi = encode_all(&data, width, channels);
+                round_trip(&data, width, channels);
+                assert!(
+                    opcodes(&qoi).ends_with(&expected_run(run)),
+                    "{:?} {}",
+                    channels,
+                    run
+                );
+            }
+        }
+    }
+}